@@ -0,0 +1,124 @@
+//! Battery definitions in JSON files, for maintaining own test suites
+
+use super::generate::GeneratorParams;
+use super::Battery;
+use crate::experiment::statement::ExperimentInfo;
+use crate::Result;
+use ::structopt::StructOpt;
+
+/// Contents of a battery definition file
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BatteryDefinition {
+    #[serde(default)]
+    pub description: Option<String>,
+
+    /// Generate experiments with these parameters, before the listed ones
+    #[serde(default)]
+    pub generator: Option<GeneratorParams>,
+
+    /// Run in this order. `session_id` and `pending_start_in_microseconds` may be omitted.
+    #[serde(default)]
+    pub experiments: Vec<ExperimentInfo>,
+}
+
+impl Battery {
+    pub fn load(p: &::std::path::Path) -> Result<Battery> {
+        let f = ::std::io::BufReader::new(::std::fs::File::open(p)?);
+        let mut d: BatteryDefinition = ::serde_json::from_reader(f)?;
+        if let Some(ref g) = d.generator {
+            let mut v = Battery::generate_with(g)?.0;
+            v.append(&mut d.experiments);
+            d.experiments = v;
+        }
+        ensure!(!d.experiments.is_empty(), "No experiments in {:?}", p);
+        for (i, e) in d.experiments.iter_mut().enumerate() {
+            ensure!(
+                e.totalpackets > 0,
+                "Experiment {} in {:?} has no packets",
+                i,
+                p
+            );
+            if let Err(msg) = e.pacing.check_limits() {
+                bail!("Experiment {} in {:?}: {}", i, p, msg);
+            }
+            // Same as `probe --profile`
+            if let Some(profile) = e.profile {
+                profile.apply(e);
+            }
+        }
+        Ok(Battery(d.experiments))
+    }
+
+    pub fn to_definition(&self, description: Option<String>) -> BatteryDefinition {
+        BatteryDefinition {
+            description,
+            generator: None,
+            experiments: self.0.clone(),
+        }
+    }
+}
+
+#[derive(StructOpt, Debug)]
+pub struct BatteryExport {
+    /// Export the big battery instead of the normal one
+    #[structopt(long = "big")]
+    big: bool,
+
+    /// Export a battery generated with the parameters below instead of a built-in one
+    #[structopt(long = "generate", conflicts_with = "big")]
+    generate: bool,
+
+    #[structopt(flatten)]
+    params: GeneratorParams,
+
+    /// Include traffic profile experiments, like `battery --profiles`
+    #[structopt(long = "profiles")]
+    profiles: bool,
+
+    /// Write to this file instead of stdout
+    #[structopt(long = "output", short = "o", parse(from_os_str))]
+    output: Option<::std::path::PathBuf>,
+}
+
+impl BatteryExport {
+    pub fn run(&self) -> Result<()> {
+        let (mut battery, kind) = if self.generate {
+            (
+                Battery::generate_with(&self.params)?,
+                format!("Generated ({:?})", self.params),
+            )
+        } else if self.big {
+            (Battery::generate_bb(), "Built-in big".to_string())
+        } else {
+            (Battery::generate(), "Built-in normal".to_string())
+        };
+        if self.profiles {
+            battery.add_profiles();
+        }
+        let description = format!(
+            "{} battery{} of netmeasure2 {}",
+            kind,
+            if self.profiles { " with profiles" } else { "" },
+            env!("CARGO_PKG_VERSION"),
+        );
+        let d = battery.to_definition(Some(description));
+        let (bytes, secs) = battery.totals();
+        eprintln!(
+            "{} experiments, {} MiB, {} minutes",
+            battery.0.len(),
+            bytes / 1024 / 1024,
+            secs / 60
+        );
+
+        let out: Box<dyn ::std::io::Write> = match self.output {
+            Some(ref pb) => Box::new(::std::fs::File::create(pb)?),
+            None => Box::new(::std::io::stdout()),
+        };
+        let mut out = ::std::io::BufWriter::new(out);
+        ::serde_json::ser::to_writer_pretty(&mut out, &d)?;
+        use ::std::io::Write;
+        writeln!(out)?;
+        out.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,112 @@
+//! Comparison of two battery results, e.g. before and after a network change
+
+use super::visualise::{load, overall_score, ScoreEntry};
+use crate::experiment::results::{ExperimentResults, ResultsForStoring};
+use crate::Result;
+use ::structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct BatteryDiff {
+    /// Earlier battery results
+    #[structopt(parse(from_os_str))]
+    old: ::std::path::PathBuf,
+
+    /// Later battery results
+    #[structopt(parse(from_os_str))]
+    new: ::std::path::PathBuf,
+}
+
+/// Deltas of one direction: ekbps, loss in percentage points, mean delay in ms
+fn format_delta(
+    old: Option<&ExperimentResults>,
+    new: Option<&ExperimentResults>,
+    entry: &ResultsForStoring,
+) -> String {
+    match (old, new) {
+        (Some(o), Some(n)) => {
+            let o = o.get_exceprt(&entry.conditions);
+            let n = n.get_exceprt(&entry.conditions);
+            format!(
+                "{:+7.0} | {:+6.1} | {:+8.1}",
+                n.ekbps - o.ekbps,
+                (n.loss - o.loss) * 100.0,
+                n.delay - o.delay,
+            )
+        }
+        (None, None) => format!("{:27}", ""),
+        _ => format!("{:27}", "missing"),
+    }
+}
+
+impl BatteryDiff {
+    pub fn run(&self) -> Result<()> {
+        let old = load(&self.old)?;
+        let new = load(&self.new)?;
+
+        // Battery generator is deterministic, but the same experiment may repeat:
+        // match n-th occurrence to n-th occurrence.
+        let mut used = vec![false; new.len()];
+        let mut old_scores = vec![];
+        let mut new_scores = vec![];
+        let mut unmatched = 0;
+
+        println!(
+            "  kbps  | pktsz || ekbps_^ | loss_^ | delay_^  || ekbps_v | loss_v | delay_v  || score"
+        );
+        for o in &old {
+            let j = (0..new.len())
+                .find(|&j| !used[j] && new[j].conditions.same_conditions(&o.conditions));
+            let n = match j {
+                Some(j) => {
+                    used[j] = true;
+                    &new[j]
+                }
+                None => {
+                    unmatched += 1;
+                    continue;
+                }
+            };
+            let (_, old_score) = o.short_summary();
+            let (_, new_score) = n.short_summary();
+            old_scores.push(ScoreEntry::new(&o.conditions, old_score));
+            new_scores.push(ScoreEntry::new(&n.conditions, new_score));
+
+            println!(
+                "{}{:6} | {:5} || {} || {} || {:2.0} -> {:2.0} ({:+.1})",
+                if o.conditions.rtpmimic { "R" } else { " " },
+                o.conditions.kbps(),
+                o.conditions.packetsize,
+                format_delta(o.to_server.as_deref(), n.to_server.as_deref(), o),
+                format_delta(o.from_server.as_deref(), n.from_server.as_deref(), o),
+                old_score,
+                new_score,
+                new_score - old_score,
+            );
+        }
+
+        let only_new = used.iter().filter(|x| !**x).count();
+        if unmatched > 0 || only_new > 0 {
+            println!(
+                "Not compared: {} experiments only in {:?}, {} only in {:?}",
+                unmatched, self.old, only_new, self.new
+            );
+        }
+        ensure!(
+            !old_scores.is_empty(),
+            "No experiments in common: batteries were generated differently",
+        );
+
+        let (old_score, old_availability) = overall_score(&old_scores);
+        let (new_score, new_availability) = overall_score(&new_scores);
+        println!(
+            "Overall network score: {:.1} -> {:.1} ({:+.1}). Availability: {:.0}% -> {:.0}% ({:+.0}%)",
+            old_score,
+            new_score,
+            new_score - old_score,
+            old_availability * 100.0,
+            new_availability * 100.0,
+            (new_availability - old_availability) * 100.0,
+        );
+        Ok(())
+    }
+}
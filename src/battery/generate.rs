@@ -1,4 +1,6 @@
 use super::Battery;
+use crate::experiment::pacing::Pacing;
+use crate::experiment::profile::ALL_PROFILES;
 use crate::experiment::results::{ExperimentResults, ResultsForStoring};
 use crate::experiment::statement::{ExperimentDirection, ExperimentInfo, ExperimentReply};
 use crate::probe::{CmdImpl, CommunicOpts};
@@ -61,6 +63,9 @@ impl Battery {
                 packetsize,
                 pending_start_in_microseconds: 2000_000,
                 rtpmimic,
+                pacing: Pacing::Constant,
+                profile: None,
+                payload: Default::default(),
                 session_id: 0,
                 totalpackets,
             };
@@ -145,6 +150,9 @@ impl Battery {
                 packetsize,
                 pending_start_in_microseconds: 2000_000,
                 rtpmimic,
+                pacing: Pacing::Constant,
+                profile: None,
+                payload: Default::default(),
                 session_id: 0,
                 totalpackets,
             };
@@ -190,4 +198,314 @@ impl Battery {
 
         Battery(v)
     }
+
+    /// Append a 10-second bidirectional experiment for each traffic profile
+    pub fn add_profiles(&mut self) {
+        for profile in ALL_PROFILES.iter() {
+            let mut e = ExperimentInfo {
+                direction: ExperimentDirection::Bidirectional,
+                packetdelay_us: 0,
+                packetsize: 0,
+                pending_start_in_microseconds: 2_000_000,
+                rtpmimic: false,
+                pacing: Pacing::Constant,
+                profile: None,
+                payload: Default::default(),
+                session_id: 0,
+                totalpackets: 0,
+            };
+            profile.apply(&mut e);
+            e.totalpackets = (10_000_000 / e.packetdelay_us) as u32;
+            self.0.push(e);
+        }
+    }
+}
+
+/// Relative weights of experiment directions, `both:send:recv`
+#[derive(Debug, Serialize, Deserialize, PartialEq, Copy, Clone)]
+#[serde(try_from = "String", into = "String")]
+pub struct DirectionMix {
+    pub bidirectional: f32,
+    pub to_server: f32,
+    pub from_server: f32,
+}
+
+impl ::std::str::FromStr for DirectionMix {
+    type Err = ::anyhow::Error;
+
+    fn from_str(s: &str) -> crate::Result<DirectionMix> {
+        let v: Vec<f32> = s
+            .split(':')
+            .map(|x| x.parse())
+            .collect::<::std::result::Result<_, _>>()?;
+        ensure!(
+            v.len() == 3 && v.iter().all(|x| *x >= 0.0) && v.iter().sum::<f32>() > 0.0,
+            "Direction mix should be three non-negative weights both:send:recv, e.g. 1:1:1"
+        );
+        Ok(DirectionMix {
+            bidirectional: v[0],
+            to_server: v[1],
+            from_server: v[2],
+        })
+    }
+}
+
+impl ::std::fmt::Display for DirectionMix {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}",
+            self.bidirectional, self.to_server, self.from_server
+        )
+    }
+}
+
+impl ::std::convert::TryFrom<String> for DirectionMix {
+    type Error = ::anyhow::Error;
+
+    fn try_from(s: String) -> crate::Result<DirectionMix> {
+        s.parse()
+    }
+}
+
+impl From<DirectionMix> for String {
+    fn from(x: DirectionMix) -> String {
+        x.to_string()
+    }
+}
+
+impl DirectionMix {
+    fn sample<R: Rng>(&self, r: &mut R) -> ExperimentDirection {
+        let x = r.gen::<f32>() * (self.bidirectional + self.to_server + self.from_server);
+        if x < self.bidirectional {
+            ExperimentDirection::Bidirectional
+        } else if x < self.bidirectional + self.to_server {
+            ExperimentDirection::ToServerOnly
+        } else {
+            ExperimentDirection::FromServerOnly
+        }
+    }
+}
+
+/// Smallest packet delay the server accepts
+const MIN_PACKETDELAY_US: u64 = 1;
+/// Faster experiments use the biggest packets, to keep packet rate sane
+const BIG_PACKETS_BELOW_DELAY_US: u64 = 40;
+/// Largest packet delay the generator uses: slow experiments get smaller packets instead
+const MAX_PACKETDELAY_US: u64 = 100_000;
+/// Experiments with less packets are not informative
+const MIN_TOTALPACKETS: u32 = 100;
+/// Largest packet size the generator uses
+const MAX_PACKETSIZE: u32 = 1536;
+/// Defaults of `serve` options `--min-packetdelay-us`, `--bwlimit` and `--timelimit`
+const SERVER_DEFAULT_LIMITS: (u64, u32, u64) = (200, 50_000, 30);
+/// Negotiation, warmup and results retrieval time of each experiment, as in `Battery::show`
+const EXPERIMENT_OVERHEAD_S: f64 = 5.0;
+
+/// Parameters of `Battery::generate_with`
+#[derive(Debug, StructOpt, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct GeneratorParams {
+    /// Total data volume to send, in both directions, megabytes
+    #[structopt(long = "volume-mb", default_value = "15")]
+    pub volume_mb: f64,
+
+    /// Smallest experiment bitrate, kbps
+    #[structopt(long = "min-kbps", default_value = "5")]
+    pub min_kbps: u32,
+
+    /// Largest experiment bitrate, kbps. Fast experiments use 1536-byte packets
+    /// with the delay they need, so the server may need smaller `--min-packetdelay-us`.
+    #[structopt(long = "max-kbps", default_value = "10000")]
+    pub max_kbps: u32,
+
+    /// Limit of the entire battery duration, seconds
+    #[structopt(long = "max-duration", default_value = "900")]
+    pub max_duration_s: u64,
+
+    /// Number of experiments
+    #[structopt(long = "experiments", default_value = "50")]
+    pub experiments: u32,
+
+    /// Number of equally populated bitrate ranges, logarithmically spaced from min to max
+    #[structopt(long = "strata", default_value = "4")]
+    pub strata: u32,
+
+    /// Relative weights of bidirectional, send-only and receive-only experiments
+    #[structopt(long = "direction-mix", default_value = "1:1:1")]
+    pub direction_mix: DirectionMix,
+
+    #[structopt(long = "seed", default_value = "1")]
+    pub seed: u64,
+}
+
+impl Default for GeneratorParams {
+    fn default() -> Self {
+        GeneratorParams::from_iter(&["generate"])
+    }
+}
+
+impl Battery {
+    /// Random battery within the given budget, stratified by bitrate
+    pub fn generate_with(p: &GeneratorParams) -> Result<Self> {
+        ensure!(
+            p.min_kbps > 0 && p.min_kbps < p.max_kbps,
+            "Bitrate range should be non-empty"
+        );
+        ensure!(
+            p.experiments > 0 && p.strata > 0,
+            "There should be some experiments and strata"
+        );
+        let fastest_kbps = (MAX_PACKETSIZE + 32) as u64 * 8 * 1000 / MIN_PACKETDELAY_US;
+        ensure!(
+            p.max_kbps as u64 <= fastest_kbps,
+            "Bitrate above {} kbps can't be reached with {}-byte packets",
+            fastest_kbps,
+            MAX_PACKETSIZE
+        );
+        let mut r = XorShiftRng::seed_from_u64(p.seed);
+        let n = p.experiments;
+        let ratio = p.max_kbps as f64 / p.min_kbps as f64;
+
+        // Step 1: shapes of experiments: packet size and rate
+        let mut v = Vec::with_capacity(n as usize);
+        for i in 0..n {
+            // Spread experiments evenly over strata
+            let stratum = (i * p.strata / n) as f64;
+            let u: f64 = r.gen();
+            let kbps = p.min_kbps as f64 * ratio.powf((stratum + u) / p.strata as f64);
+
+            let mut packetsize = if r.gen_bool(0.5) {
+                r.gen_range(100, 1537)
+            } else {
+                r.gen_range(32, 100)
+            };
+            // Inverse of `ExperimentInfo::kbps`
+            let delay_for = |packetsize: u32| {
+                let effective_ps = (packetsize + 32).max(64) as f64;
+                (effective_ps * 8.0 * 1000.0 / kbps) as u64
+            };
+            if delay_for(packetsize) < BIG_PACKETS_BELOW_DELAY_US {
+                packetsize = MAX_PACKETSIZE;
+            }
+            if delay_for(packetsize) > MAX_PACKETDELAY_US {
+                packetsize = 32;
+            }
+            let packetdelay_us =
+                delay_for(packetsize).clamp(MIN_PACKETDELAY_US, MAX_PACKETDELAY_US);
+
+            v.push(ExperimentInfo {
+                direction: p.direction_mix.sample(&mut r),
+                packetdelay_us,
+                packetsize,
+                pending_start_in_microseconds: 2_000_000,
+                rtpmimic: r.gen(),
+                pacing: Pacing::Constant,
+                profile: None,
+                payload: Default::default(),
+                session_id: 0,
+                totalpackets: 0,
+            });
+        }
+
+        // Step 2: number of packets. Slow experiments are limited by time and leave
+        // unused volume to faster ones. Each experiment gets an equal share of what
+        // the previous ones left, so the minimum number of packets is paid by later ones.
+        v.sort_by_key(|e| e.kbps());
+        let overhead_s = n as f64 * EXPERIMENT_OVERHEAD_S;
+        ensure!(
+            overhead_s < p.max_duration_s as f64,
+            "{} experiments take at least {} seconds, more than --max-duration",
+            n,
+            overhead_s
+        );
+        let mut remaining_s = p.max_duration_s as f64 - overhead_s;
+        let mut remaining_bytes = p.volume_mb * 1024.0 * 1024.0;
+        for (i, e) in v.iter_mut().enumerate() {
+            let left = (n as usize - i) as f64;
+            let copies = if e.direction == ExperimentDirection::Bidirectional {
+                2.0
+            } else {
+                1.0
+            };
+            let by_volume =
+                remaining_bytes.max(0.0) / left / copies / (e.mean_packetsize() + 24) as f64;
+            let by_time = remaining_s.max(0.0) / left * 1_000_000.0 / e.packetdelay_us as f64;
+            e.totalpackets = (by_volume.min(by_time) as u32).max(MIN_TOTALPACKETS);
+            remaining_bytes -= e.bytes_used() as f64 * copies;
+            remaining_s -= e.duration().as_secs_f64();
+        }
+
+        // Step 3: minimum number of packets may overshoot a tight budget
+        let battery = Battery(v);
+        let (bytes, secs) = battery.totals();
+        ensure!(
+            bytes as f64 <= p.volume_mb * 1024.0 * 1024.0 && secs <= p.max_duration_s,
+            "{} experiments of at least {} packets need {:.1} MB and {} seconds, \
+             over the budget. Use fewer --experiments or bigger --min-kbps.",
+            n,
+            MIN_TOTALPACKETS,
+            bytes as f64 / 1024.0 / 1024.0,
+            secs
+        );
+        warn_server_limits(&battery);
+        let mut v = battery.0;
+        v[..].shuffle(&mut r);
+
+        Ok(Battery(v))
+    }
+}
+
+/// Tell which `serve` options a battery needs beyond the defaults
+fn warn_server_limits(b: &Battery) {
+    let (default_delay, default_bw, default_time) = SERVER_DEFAULT_LIMITS;
+    let min_delay =
+        b.0.iter()
+            .map(|e| e.packetdelay_us)
+            .min()
+            .unwrap_or(default_delay);
+    let max_kbps = b.0.iter().map(|e| e.kbps()).max().unwrap_or(0);
+    let max_secs =
+        b.0.iter()
+            .map(|e| e.duration().as_secs() + 1)
+            .max()
+            .unwrap_or(0);
+    let mut opts = vec![];
+    if min_delay < default_delay {
+        opts.push(format!("--min-packetdelay-us {}", min_delay));
+    }
+    if max_kbps > default_bw {
+        opts.push(format!("--bwlimit {}", max_kbps + 1));
+    }
+    if max_secs > default_time {
+        opts.push(format!("--timelimit {}", max_secs));
+    }
+    if !opts.is_empty() {
+        eprintln!(
+            "Note: the generated battery needs server started with {}",
+            opts.join(" ")
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn big_budget_is_respected() {
+        // Gigabit link: several GiB, more than u32 bytes
+        let p = GeneratorParams {
+            volume_mb: 20_000.0,
+            max_kbps: 1_000_000,
+            max_duration_s: 3600,
+            experiments: 20,
+            ..Default::default()
+        };
+        let b = Battery::generate_with(&p).unwrap();
+        let (bytes, secs) = b.totals();
+        assert!(bytes > u32::MAX as u64);
+        assert!(bytes as f64 <= p.volume_mb * 1024.0 * 1024.0);
+        assert!(secs <= p.max_duration_s);
+    }
 }
@@ -0,0 +1,391 @@
+//! Self-contained HTML report of battery results, with inline SVG charts
+
+use super::visualise::{overall_score, sort_key, ScoreEntry, SortOrder};
+use crate::experiment::results::{ExperimentResults, ResultsForStoring, CLUSTERS, DELAY_VALUES};
+use crate::Result;
+use ::std::fmt::Write;
+
+const UP_COLOR: &str = "#d62728";
+const DOWN_COLOR: &str = "#1f77b4";
+
+const CHART_W: f32 = 640.0;
+const CHART_H: f32 = 260.0;
+const SMALL_W: f32 = 300.0;
+const SMALL_H: f32 = 140.0;
+/// Space for axis labels
+const MARGIN_L: f32 = 50.0;
+const MARGIN_B: f32 = 36.0;
+const MARGIN_T: f32 = 24.0;
+const MARGIN_R: f32 = 12.0;
+
+fn esc(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+struct Series<'a> {
+    name: &'a str,
+    color: &'a str,
+    points: Vec<(f32, f32)>,
+}
+
+/// Round number not less than `x`, for axis maximums
+fn nice_ceiling(x: f32) -> f32 {
+    if x <= 0.0 {
+        return 1.0;
+    }
+    let mag = 10f32.powf(x.log10().floor());
+    for m in &[1.0, 2.0, 5.0, 10.0] {
+        if m * mag >= x {
+            return m * mag;
+        }
+    }
+    10.0 * mag
+}
+
+/// Line chart with logarithmic X axis
+fn line_chart(title: &str, x_label: &str, y_label: &str, series: &[Series]) -> String {
+    let all = || {
+        series
+            .iter()
+            .flat_map(|s| s.points.iter())
+            .filter(|p| p.0.is_finite() && p.1.is_finite())
+    };
+    if all().next().is_none() {
+        return String::new();
+    }
+    let x_min = all().map(|p| p.0).fold(f32::MAX, f32::min).max(1.0);
+    let x_max = all().map(|p| p.0).fold(0.0, f32::max).max(x_min * 10.0);
+    let y_max = nice_ceiling(all().map(|p| p.1).fold(0.0, f32::max));
+    let (lx_min, lx_max) = (10f32.powf(x_min.log10().floor()), x_max);
+    // Tick loops below would not end otherwise
+    assert!(lx_min.is_finite() && lx_max.is_finite() && y_max.is_finite());
+
+    let pw = CHART_W - MARGIN_L - MARGIN_R;
+    let ph = CHART_H - MARGIN_T - MARGIN_B;
+    let sx = |x: f32| {
+        MARGIN_L + (x.max(lx_min).log10() - lx_min.log10()) / (lx_max.log10() - lx_min.log10()) * pw
+    };
+    let sy = |y: f32| MARGIN_T + ph - y / y_max * ph;
+
+    let mut s = String::new();
+    let _ = write!(
+        s,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" font-family="sans-serif" font-size="11">"#,
+        w = CHART_W,
+        h = CHART_H
+    );
+    let _ = write!(
+        s,
+        r#"<text x="{}" y="15" font-size="13" font-weight="bold">{}</text>"#,
+        MARGIN_L,
+        esc(title)
+    );
+
+    // Grid and ticks
+    for i in 0..=5 {
+        let y = (y_max * i as f32 / 5.0 * 100.0).round() / 100.0;
+        let _ = write!(
+            s,
+            r##"<line x1="{x1}" x2="{x2}" y1="{y}" y2="{y}" stroke="#ddd"/><text x="{tx}" y="{ty}" text-anchor="end">{v}</text>"##,
+            x1 = MARGIN_L,
+            x2 = MARGIN_L + pw,
+            y = sy(y),
+            tx = MARGIN_L - 4.0,
+            ty = sy(y) + 4.0,
+            v = y,
+        );
+    }
+    let mut decade = lx_min;
+    while decade <= lx_max {
+        let _ = write!(
+            s,
+            r##"<line x1="{x}" x2="{x}" y1="{y1}" y2="{y2}" stroke="#ddd"/><text x="{x}" y="{ty}" text-anchor="middle">{v}</text>"##,
+            x = sx(decade),
+            y1 = MARGIN_T,
+            y2 = MARGIN_T + ph,
+            ty = MARGIN_T + ph + 14.0,
+            v = decade,
+        );
+        decade *= 10.0;
+    }
+    let _ = write!(
+        s,
+        r#"<text x="{}" y="{}" text-anchor="middle">{}</text>"#,
+        MARGIN_L + pw / 2.0,
+        CHART_H - 4.0,
+        esc(x_label)
+    );
+    let _ = write!(
+        s,
+        r#"<text x="12" y="{y}" text-anchor="middle" transform="rotate(-90 12 {y})">{}</text>"#,
+        esc(y_label),
+        y = MARGIN_T + ph / 2.0,
+    );
+
+    for (n, ser) in series.iter().enumerate() {
+        let mut pts: Vec<(f32, f32)> = ser
+            .points
+            .iter()
+            .filter(|p| p.0.is_finite() && p.1.is_finite())
+            .copied()
+            .collect();
+        pts.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(::std::cmp::Ordering::Equal));
+        let path: Vec<String> = pts
+            .iter()
+            .map(|&(x, y)| format!("{:.1},{:.1}", sx(x), sy(y)))
+            .collect();
+        let _ = write!(
+            s,
+            r#"<polyline fill="none" stroke="{}" stroke-width="1.5" points="{}"/>"#,
+            ser.color,
+            path.join(" ")
+        );
+        for &(x, y) in &pts {
+            let _ = write!(
+                s,
+                r#"<circle cx="{:.1}" cy="{:.1}" r="2.5" fill="{}"><title>{:.0} kbps: {:.2}</title></circle>"#,
+                sx(x),
+                sy(y),
+                ser.color,
+                x,
+                y
+            );
+        }
+        let lx = MARGIN_L + pw - 150.0 + 80.0 * n as f32;
+        let _ = write!(
+            s,
+            r#"<rect x="{}" y="6" width="10" height="10" fill="{}"/><text x="{}" y="15">{}</text>"#,
+            lx,
+            ser.color,
+            lx + 14.0,
+            esc(ser.name)
+        );
+    }
+    s.push_str("</svg>");
+    s
+}
+
+/// Small bar chart of a normalized histogram, only buckets from first to last non-empty one
+fn histogram(title: &str, labels: &[String], values: &[f32], color: &str) -> String {
+    let first = values.iter().position(|&x| x > 0.0005).unwrap_or(0);
+    let last = values.iter().rposition(|&x| x > 0.0005).unwrap_or(0);
+    let (labels, values) = (&labels[first..=last], &values[first..=last]);
+    let y_max = values.iter().cloned().fold(0.0, f32::max).max(1e-6);
+
+    let top = 18.0;
+    let bottom = 18.0;
+    let ph = SMALL_H - top - bottom;
+    let bw = (SMALL_W - 8.0) / values.len() as f32;
+
+    let mut s = String::new();
+    let _ = write!(
+        s,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" font-family="sans-serif" font-size="9">"#,
+        SMALL_W, SMALL_H
+    );
+    let _ = write!(
+        s,
+        r#"<text x="4" y="12" font-size="11">{}</text>"#,
+        esc(title)
+    );
+    // Label every few bars, so they don't overlap
+    let label_every = ((24.0 / bw).ceil() as usize).max(1);
+    for (i, (&v, label)) in values.iter().zip(labels).enumerate() {
+        let h = v / y_max * ph;
+        let x = 4.0 + i as f32 * bw;
+        let _ = write!(
+            s,
+            r#"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" fill="{}"><title>{}: {:.4}</title></rect>"#,
+            x,
+            top + ph - h,
+            (bw - 1.0).max(1.0),
+            h,
+            color,
+            esc(label),
+            v
+        );
+        if i % label_every == 0 {
+            let _ = write!(
+                s,
+                r#"<text x="{:.1}" y="{}" text-anchor="middle">{}</text>"#,
+                x + bw / 2.0,
+                SMALL_H - 6.0,
+                esc(label)
+            );
+        }
+    }
+    s.push_str("</svg>");
+    s
+}
+
+/// Last bucket of histograms means "more than the previous one"
+fn bucket_labels(registry: &[i32]) -> Vec<String> {
+    registry
+        .iter()
+        .map(|&x| {
+            if x == 65535 {
+                "more".to_string()
+            } else {
+                x.to_string()
+            }
+        })
+        .collect()
+}
+
+fn direction_details(s: &mut String, name: &str, color: &str, r: &ExperimentResults) {
+    let dm = &r.delay_model;
+    let delay_labels = bucket_labels(&DELAY_VALUES);
+    let cluster_labels = bucket_labels(&CLUSTERS);
+    let _ = write!(
+        s,
+        "<div class=\"dir\"><b>{}</b>: delay p50/p90/p99/max {:.0}/{:.0}/{:.0}/{:.0} ms, jitter {:.1} ms<br>",
+        name, dm.p50_ms, dm.p90_ms, dm.p99_ms, dm.max_ms, dm.jitter_ms
+    );
+    s.push_str(&histogram(
+        "Delay, ms",
+        &delay_labels,
+        &dm.value_popularity,
+        color,
+    ));
+    if r.loss_model.loss_prob > 0.0 {
+        s.push_str(&histogram(
+            "Loss clusters, packets",
+            &cluster_labels,
+            &r.loss_model.loss,
+            color,
+        ));
+    }
+    s.push_str("</div>");
+}
+
+/// Write HTML report of battery results
+pub fn write_report(
+    v: &[ResultsForStoring],
+    sort_order: SortOrder,
+    title: &str,
+    out: &mut dyn ::std::io::Write,
+) -> Result<()> {
+    let mut order: Vec<usize> = (0..v.len()).collect();
+    order.sort_by_key(|&i| sort_key(&v[i], i, sort_order));
+
+    let mut scores = vec![];
+    let mut loss = [vec![], vec![]];
+    let mut delay = [vec![], vec![]];
+    for &i in &order {
+        let entry = &v[i];
+        scores.push(ScoreEntry::new(&entry.conditions, entry.short_summary().1));
+        let kbps = entry.conditions.kbps() as f32;
+        for (d, r) in [&entry.to_server, &entry.from_server].iter().enumerate() {
+            if let Some(r) = r {
+                loss[d].push((kbps, r.loss_model.loss_prob * 100.0));
+                delay[d].push((kbps, r.delay_model.mean_delay_ms));
+            }
+        }
+    }
+    let (score, availability) = overall_score(&scores);
+    let [loss_up, loss_down] = loss;
+    let [delay_up, delay_down] = delay;
+
+    let mut s = String::new();
+    let _ = write!(
+        s,
+        r#"<!DOCTYPE html>
+<html><head><meta charset="utf-8"><title>{title}</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; color: #222; }}
+table {{ border-collapse: collapse; font-size: 13px; }}
+td, th {{ border: 1px solid #ccc; padding: 2px 6px; text-align: right; }}
+td.text {{ text-align: left; font-family: monospace; white-space: pre; }}
+.score {{ font-size: 1.6em; }}
+.dir {{ display: inline-block; vertical-align: top; margin: 4px 16px 4px 0; }}
+</style></head><body>
+<h1>{title}</h1>
+<p class="score">Overall network score: <b>{score:.1}</b> of 10. Availability: <b>{avail:.0}%</b></p>
+<p>{n} experiments. Upload (client to server) is <span style="color:{up}">red</span>,
+download (server to client) is <span style="color:{down}">blue</span>.
+Generated by netmeasure2 {version}.</p>
+"#,
+        title = esc(title),
+        score = score,
+        avail = availability * 100.0,
+        n = v.len(),
+        up = UP_COLOR,
+        down = DOWN_COLOR,
+        version = env!("CARGO_PKG_VERSION"),
+    );
+
+    s.push_str(&line_chart(
+        "Loss vs bitrate",
+        "kbps",
+        "loss, %",
+        &[
+            Series {
+                name: "upload",
+                color: UP_COLOR,
+                points: loss_up,
+            },
+            Series {
+                name: "download",
+                color: DOWN_COLOR,
+                points: loss_down,
+            },
+        ],
+    ));
+    s.push_str("<br>");
+    s.push_str(&line_chart(
+        "Mean delay vs bitrate",
+        "kbps",
+        "delay, ms",
+        &[
+            Series {
+                name: "upload",
+                color: UP_COLOR,
+                points: delay_up,
+            },
+            Series {
+                name: "download",
+                color: DOWN_COLOR,
+                points: delay_down,
+            },
+        ],
+    ));
+
+    s.push_str("<h2>Experiments</h2>\n<table>\n<tr><th>kbps</th><th>packet size</th><th>summary</th><th>score</th></tr>\n");
+    for &i in &order {
+        let entry = &v[i];
+        let (text, quality_score) = entry.short_summary();
+        let _ = write!(
+            s,
+            "<tr><td>{}</td><td>{}</td><td class=\"text\"><details><summary>{}</summary>",
+            entry.conditions.kbps(),
+            entry.conditions.packetsize,
+            esc(&text),
+        );
+        if let Some(ref r) = entry.to_server {
+            direction_details(&mut s, "Upload", UP_COLOR, r);
+        }
+        if let Some(ref r) = entry.from_server {
+            direction_details(&mut s, "Download", DOWN_COLOR, r);
+        }
+        let _ = writeln!(s, "</details></td><td>{:.1}</td></tr>", quality_score);
+    }
+    s.push_str("</table>\n</body></html>\n");
+
+    out.write_all(s.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_battery() {
+        let mut out = vec![];
+        write_report(&[], SortOrder::Kbps, "empty", &mut out).unwrap();
+        assert!(!String::from_utf8(out).unwrap().contains("<svg"));
+    }
+}
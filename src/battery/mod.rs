@@ -5,7 +5,10 @@ use crate::experiment::statement::{ExperimentDirection, ExperimentInfo, Experime
 use crate::Result;
 use ::structopt::StructOpt;
 
+pub mod definition;
+pub mod diff;
 pub mod generate;
+pub mod html;
 pub mod run;
 pub mod visualise;
 
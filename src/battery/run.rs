@@ -1,3 +1,4 @@
+use super::visualise::{print_legend, print_table, SortOrder};
 use super::Battery;
 use crate::experiment::results::{ExperimentResults, ResultsForStoring};
 use crate::experiment::statement::{ExperimentDirection, ExperimentInfo, ExperimentReply};
@@ -5,6 +6,8 @@ use crate::probe::{CmdImpl, CommunicOpts};
 use crate::Result;
 use ::rand::{Rng, RngCore, SeedableRng};
 use ::rand_xorshift::XorShiftRng;
+use ::std::io::{BufRead, Write};
+use ::std::path::{Path, PathBuf};
 use ::structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
@@ -20,6 +23,10 @@ pub struct Cmd {
     #[structopt(short = "S")]
     visualise: bool,
 
+    /// With -S, also show detailed histograms of each experiment
+    #[structopt(long = "verbose", short = "v")]
+    verbose: bool,
+
     /// Do a big, half-a-gigabyte test for more broadband networks
     #[structopt(long = "big")]
     big: bool,
@@ -28,6 +35,14 @@ pub struct Cmd {
     #[structopt(long = "small")]
     small: bool,
 
+    /// Run experiments listed in this file (see `batexport`) instead of a built-in battery
+    #[structopt(long = "battery-file", parse(from_os_str), conflicts_with = "big")]
+    battery_file: Option<::std::path::PathBuf>,
+
+    /// Also run experiments emulating each traffic profile (voice, video, game)
+    #[structopt(long = "profiles")]
+    profiles: bool,
+
     /// Maximum number of retries if non-first experiment is failed
     #[structopt(long = "max-retries", default_value = "4")]
     max_retries: usize,
@@ -35,6 +50,49 @@ pub struct Cmd {
     /// Wait this number of seconds after single experiment failure before retrying
     #[structopt(long = "wait-before-retry", default_value = "30")]
     wait_before_retry: u64,
+
+    /// Continue an interrupted battery: skip experiments already saved
+    /// in `<output>.partial`. Requires -o.
+    #[structopt(long = "resume")]
+    resume: bool,
+
+    #[structopt(flatten)]
+    record: crate::history::RecordOpts,
+}
+
+/// Completed experiments of an unfinished battery are kept next to the output file,
+/// one JSON per line
+fn partial_path(output: &Path) -> PathBuf {
+    let mut p = output.as_os_str().to_owned();
+    p.push(".partial");
+    p.into()
+}
+
+fn load_partial(p: &Path) -> Result<Vec<ResultsForStoring>> {
+    let f = match ::std::fs::File::open(p) {
+        Ok(f) => ::std::io::BufReader::new(f),
+        Err(e) if e.kind() == ::std::io::ErrorKind::NotFound => {
+            eprintln!("No {:?}, starting from the beginning", p);
+            return Ok(vec![]);
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let mut v = vec![];
+    for line in f.lines() {
+        // Last line may be torn by interruption
+        if let Ok(r) = ::serde_json::from_str(&line?) {
+            v.push(r);
+        }
+    }
+    Ok(v)
+}
+
+fn save_partial(f: &mut ::std::fs::File, r: &ResultsForStoring) -> Result<()> {
+    let mut line = ::serde_json::to_string(r)?;
+    line.push('\n');
+    f.write_all(line.as_bytes())?;
+    f.sync_data()?;
+    Ok(())
 }
 
 impl Cmd {
@@ -42,19 +100,58 @@ impl Cmd {
         let cmd = self;
         let mut v = vec![];
 
-        let battery = if cmd.big {
+        let mut battery = if let Some(ref p) = cmd.battery_file {
+            Battery::load(p)?
+        } else if cmd.big {
             Battery::generate_bb()
         } else {
             Battery::generate()
         };
+        if cmd.profiles {
+            battery.add_profiles();
+        }
 
         let n = battery.0.len();
         let co = cmd.co;
+        let server = co.server;
+
+        ensure!(!cmd.resume || cmd.output.is_some(), "--resume requires -o");
+        let partial = cmd.output.as_deref().map(partial_path);
+        let mut done = vec![];
+        let mut checkpoint = None;
+        if let Some(ref p) = partial {
+            if cmd.resume {
+                done = load_partial(p)?;
+            } else {
+                ensure!(
+                    !p.exists(),
+                    "There is {:?} from an interrupted battery. Use --resume to continue it or remove the file.",
+                    p
+                );
+            }
+            // Rewritten without a torn line, if any
+            let mut f = ::std::fs::File::create(p)?;
+            for r in &done {
+                save_partial(&mut f, r)?;
+            }
+            checkpoint = Some(f);
+        }
+        // Experiments run (not resumed) in this invocation
+        let mut ran = 0;
 
         eprintln!("0%");
         for (i, experiment) in battery.0.into_iter().enumerate() {
             use crate::probe::probe_impl;
 
+            if let Some(j) = done
+                .iter()
+                .position(|x| x.conditions.same_conditions(&experiment))
+            {
+                v.push(done.remove(j));
+                eprintln!("{}% (resumed)", (i + 1) * 100 / n);
+                continue;
+            }
+
             let mut retries = 0;
 
             let ci = CmdImpl {
@@ -65,17 +162,21 @@ impl Cmd {
             loop {
                 match probe_impl(ci.clone()) {
                     Ok(r) => {
+                        if let Some(ref mut f) = checkpoint {
+                            save_partial(f, &r)?;
+                        }
                         v.push(r);
+                        ran += 1;
                         break;
                     }
                     Err(e) => {
                         eprintln!("Error: {}", e);
-                        if i < 3 {
+                        if ran < 3 {
                             if format!("{}", e).contains("busy") {
                                 bail!("Server is probably busy with another session");
                             }
                         }
-                        if i == 0 {
+                        if ran == 0 {
                             bail!("First experiment failed")
                         }
                         retries += 1;
@@ -95,23 +196,41 @@ impl Cmd {
             eprintln!("{}%", (i + 1) * 100 / n);
         }
 
-        if cmd.visualise && cmd.output.is_none() {
-            println!("Visualise not implemented");
-        } else {
-            let out: Box<dyn (::std::io::Write)>;
-            if let Some(pb) = cmd.output {
-                let f = ::std::fs::File::create(pb)?;
-                out = Box::new(f);
-                if cmd.visualise {
-                    println!("Visualise not implemented");
-                }
-            } else {
-                out = Box::new(::std::io::stdout());
-            }
+        if !done.is_empty() {
+            eprintln!(
+                "{} experiments from {:?} are not in this battery, dropped",
+                done.len(),
+                partial
+            );
+        }
+
+        let out: Option<Box<dyn ::std::io::Write>> = match cmd.output {
+            Some(pb) => Some(Box::new(::std::fs::File::create(pb)?)),
+            // The table replaces JSON on stdout
+            None if cmd.visualise => None,
+            None => Some(Box::new(::std::io::stdout())),
+        };
+        if let Some(out) = out {
             let mut out = ::std::io::BufWriter::new(out);
             ::serde_json::ser::to_writer(&mut out, &v)?;
-            use ::std::io::Write;
             writeln!(out)?;
+            out.flush()?;
+        }
+        if let Some(p) = partial {
+            ::std::fs::remove_file(p)?;
+        }
+
+        if cmd.visualise {
+            print_table(&v, cmd.verbose, SortOrder::Kbps);
+            println!();
+            print_legend();
+        }
+
+        // Results are already out, so a broken history store should not lose them
+        if let Err(e) =
+            crate::history::record(&cmd.record, &server, crate::history::RunKind::Battery, &v)
+        {
+            eprintln!("Warning: failed to record to history: {}", e);
         }
 
         Ok(())
@@ -34,15 +34,36 @@ pub struct BatteryShow {
     /// Show legend for symbols used in output (not including verose
     #[structopt(long = "legend")]
     legend: bool,
+
+    /// Write self-contained HTML report with charts to this file instead
+    #[structopt(long = "html", parse(from_os_str))]
+    html: Option<::std::path::PathBuf>,
 }
 
 impl BatteryShow {
     pub fn run(&self) -> Result<()> {
+        if let Some(ref out) = self.html {
+            let v = load(&self.file)?;
+            let title = format!("Network quality report: {}", self.file.display());
+            let mut f = ::std::io::BufWriter::new(::std::fs::File::create(out)?);
+            super::html::write_report(&v, self.sort, &title, &mut f)?;
+            use ::std::io::Write;
+            f.flush()?;
+            return Ok(());
+        }
         print_summary(&self.file, self.verbose, self.sort)?;
         if self.legend {
-            print!(
-                r#"## Legend: ##
+            print_legend();
+        }
+        Ok(())
+    }
+}
+
+pub fn print_legend() {
+    print!(
+        r#"## Legend: ##
 `R` at the beginning - RTP simulation is on for this test
+`MOS` after score - ITU-T G.107 E-model voice quality estimate (RTP simulation only)
 Symbols after loss percentage:
 `*` - there is send-side loss - `sentdo` syscall takes too long to finish
 `r`, `R` - loss should be recoverable by FEC
@@ -50,15 +71,15 @@ Symbols after loss percentage:
 `+` - although loss percentage is low, it came in non-FEC-recoverable cluster
 `$` - significant loss happened at the end of test and 
       is not counted towards normal loss per cent
+`D` - some packets were received more than once
+`C` - some packets arrived with corrupted or modified payload
 Symbols after delay value:
 `.` `l` `L` `LL` - "latch-ups" - sudden sharp increases in delay
 `,` `r`, `R` - "recoveries" - quick decreasings of delay
 `lr`, `Lr`, etc. - combination of two above
+Line below each test: delay percentiles p50/p90/p99/max and `~` RFC 3550 jitter, ms
 "#
-            );
-        }
-        Ok(())
-    }
+    );
 }
 
 // Summary of one half-experiment
@@ -72,7 +93,14 @@ pub struct Excerpt {
     pub loss_sendside_precise: f32,
     pub loss_recoverability: char,
     pub loss_at_the_end: bool,
+    pub duplicates: bool,
+    pub corrupted: bool,
     pub delay: f32,
+    pub delay_p50: f32,
+    pub delay_p90: f32,
+    pub delay_p99: f32,
+    pub delay_max: f32,
+    pub jitter: f32,
     pub latchup_marker: &'static str,
 }
 
@@ -84,17 +112,35 @@ impl Excerpt {
             format!("{:4.1}", self.loss * 100.0)
         };
         format!(
-            "{:7.0} | {}{}{}{}| {:7.0} {}",
+            "{:7.0} | {}{}{}{}{}| {:7.0} {}",
             self.ekbps,
             loss,
             if self.loss_sendside { '*' } else { ' ' },
             self.loss_recoverability,
             if self.loss_at_the_end { '$' } else { ' ' },
+            if self.corrupted {
+                'C'
+            } else if self.duplicates {
+                'D'
+            } else {
+                ' '
+            },
             self.delay,
             self.latchup_marker,
         )
     }
 
+    /// Delay distribution: `p50/p90/p99/max ~jitter`, ms. Empty for results without percentiles.
+    pub fn format_delay_spread(&self) -> String {
+        if self.delay_max <= 0.0 {
+            return String::new();
+        }
+        format!(
+            "{:.0}/{:.0}/{:.0}/{:.0} ~{:.1}",
+            self.delay_p50, self.delay_p90, self.delay_p99, self.delay_max, self.jitter,
+        )
+    }
+
     /// From 0.0 to 10.0
     pub fn quality_score(&self) -> f32 {
         let mut loss_karma = 0.0;
@@ -232,7 +278,14 @@ impl ExperimentResults {
             loss_sendside,
             loss_recoverability,
             loss_at_the_end,
+            duplicates: lm.duplicates > 0,
+            corrupted: lm.corrupted > 0,
             delay,
+            delay_p50: x.delay_model.p50_ms,
+            delay_p90: x.delay_model.p90_ms,
+            delay_p99: x.delay_model.p99_ms,
+            delay_max: x.delay_model.max_ms,
+            jitter: x.delay_model.jitter_ms,
             latchup_marker,
             loss_sendside_precise: self.loss_model.sendside_loss,
             badloss,
@@ -247,28 +300,55 @@ impl ResultsForStoring {
 
         let mut toserv = format!("");
         let mut fromserv = format!("");
+        let mut toserv_spread = String::new();
+        let mut fromserv_spread = String::new();
         let mut score = 10.0f32;
+        let mut mos: Option<f32> = None;
         let mut q = |x: &ExperimentResults| {
             let e = x.get_exceprt(&entry.conditions);
             score = score.min(e.quality_score());
-            e.format()
+            if entry.conditions.rtpmimic {
+                // Only round trip time is known, assume symmetric path
+                let m = x.voice_quality(entry.rtt_us as f32 / 2000.0).mos;
+                mos = Some(mos.map_or(m, |y| y.min(m)));
+            }
+            (e.format(), e.format_delay_spread())
         };
         if let Some(x) = entry.to_server.as_ref() {
-            toserv = q(x);
+            (toserv, toserv_spread) = q(x);
         }
         if let Some(x) = entry.from_server.as_ref() {
-            fromserv = q(x);
+            (fromserv, fromserv_spread) = q(x);
         }
+        let spread = if toserv_spread.is_empty() && fromserv_spread.is_empty() {
+            String::new()
+        } else {
+            format!(
+                "\n{:16}|| {:30} || {:30}||",
+                "", toserv_spread, fromserv_spread
+            )
+        };
         let rtpmim = if entry.conditions.rtpmimic { "R" } else { " " };
+        let profile = match entry.conditions.profile {
+            Some(p) => format!(" {}", p),
+            None => String::new(),
+        };
+        let mos = match mos {
+            Some(m) => format!(" MOS {:.1}", m),
+            None => String::new(),
+        };
         (
             format!(
-                "{}{:6} | {:5} || {:29} || {:29}|| {:2.0}",
+                "{}{:6} | {:5} || {:30} || {:30}|| {:2.0}{}{}{}",
                 rtpmim,
                 entry.conditions.kbps(),
                 entry.conditions.packetsize,
                 toserv,
                 fromserv,
                 score,
+                mos,
+                profile,
+                spread,
             ),
             score,
         )
@@ -276,9 +356,21 @@ impl ResultsForStoring {
 }
 
 impl Battery {
-    pub fn show(&self) {
+    /// Data volume in bytes and duration in seconds of entire battery, including overheads
+    pub fn totals(&self) -> (u64, u64) {
         let mut b = 0u64;
         let mut t = 0u64;
+        for i in &self.0 {
+            b += i.bytes_used();
+            t += i.duration().as_secs() + 5;
+            if i.direction == ExperimentDirection::Bidirectional {
+                b += i.bytes_used(); // once more
+            }
+        }
+        (b, t)
+    }
+
+    pub fn show(&self) {
         for i in &self.0 {
             println!(
                 "{:.3}mbps {}s {:?}",
@@ -286,12 +378,8 @@ impl Battery {
                 i.duration().as_secs(),
                 i
             );
-            b += i.bytes_used() as u64;
-            t += i.duration().as_secs() + 5;
-            if i.direction == ExperimentDirection::Bidirectional {
-                b += i.bytes_used() as u64; // once more
-            }
         }
+        let (b, t) = self.totals();
         println!(
             "Total {} MiB, {} minutes, {} experiments",
             b / 1024 / 1024,
@@ -301,43 +389,17 @@ impl Battery {
     }
 }
 
-pub fn print_summary(p: &::std::path::Path, verbose: bool, sort_order: SortOrder) -> Result<()> {
-    let f = ::std::io::BufReader::new(::std::fs::File::open(p)?);
-    let v: Vec<ResultsForStoring> = ::serde_json::from_reader(f)?;
-
-    use ::std::collections::BTreeMap;
-    let mut m: BTreeMap<u64, usize> = BTreeMap::new();
-
-    for (i, entry) in v.iter().enumerate() {
-        let sortkey = match sort_order {
-            SortOrder::Kbps => entry.conditions.kbps() as u64,
-            SortOrder::Time => i as u64,
-            SortOrder::PktRate => entry.conditions.packetdelay_us as u64,
-            SortOrder::PktSize => entry.conditions.packetsize as u64,
-        };
-        m.insert(sortkey, i);
-    }
-
-    struct ScoreEntry {
-        val: f32,
-        weight: f32,
-    };
-    let mut scores: Vec<ScoreEntry> = Vec::with_capacity(40);
-
-    println!(
-        "  kbps  | pktsz || ekbps_^ | loss_^ | delay_^    || ekbps_v | loss_v | delay_v   || score"
-    );
-    for (_, &i) in m.iter() {
-        let entry = &v[i];
-        let (text, quality_score) = entry.short_summary();
-        println!("{}", text);
-        if verbose {
-            entry.print_to_stdout();
-        }
+/// Quality score of one experiment and its contribution to the overall score
+pub struct ScoreEntry {
+    pub val: f32,
+    pub weight: f32,
+}
 
-        scores.push(ScoreEntry {
-            val: quality_score,
-            weight: match entry.conditions.kbps() {
+impl ScoreEntry {
+    pub fn new(conditions: &ExperimentInfo, val: f32) -> Self {
+        ScoreEntry {
+            val,
+            weight: match conditions.kbps() {
                 x if x < 100 => 1.0,
                 x if x < 400 => 0.9,
                 x if x < 1000 => 0.8,
@@ -348,14 +410,17 @@ pub fn print_summary(p: &::std::path::Path, verbose: bool, sort_order: SortOrder
                 x if x < 30_000 => 0.2,
                 _ => 0.1,
             },
-        })
+        }
     }
+}
 
+/// Overall network score and availability (from 0 to 1)
+pub fn overall_score(scores: &[ScoreEntry]) -> (f32, f32) {
     let mut availability = 0.0;
     let mut availability_total = 0.0001;
     let mut sum = 0.0;
     let mut weightsum = 0.0;
-    for ScoreEntry { val, mut weight } in scores {
+    for &ScoreEntry { val, mut weight } in scores {
         if weight >= 0.3 {
             if val < 8.0 {
                 weight *= 2.0;
@@ -383,19 +448,66 @@ pub fn print_summary(p: &::std::path::Path, verbose: bool, sort_order: SortOrder
         sum += val * weight;
         weightsum += weight;
     }
-    let overall_score = sum / weightsum;
+    (sum / weightsum, availability / availability_total)
+}
+
+/// Key to order battery entries by. `i` is position in the file.
+pub fn sort_key(entry: &ResultsForStoring, i: usize, sort_order: SortOrder) -> u64 {
+    match sort_order {
+        SortOrder::Kbps => entry.conditions.kbps() as u64,
+        SortOrder::Time => i as u64,
+        SortOrder::PktRate => entry.conditions.packetdelay_us as u64,
+        SortOrder::PktSize => entry.conditions.packetsize as u64,
+    }
+}
+
+/// Read battery results saved by `battery -o`
+pub fn load(p: &::std::path::Path) -> Result<Vec<ResultsForStoring>> {
+    let f = ::std::io::BufReader::new(::std::fs::File::open(p)?);
+    Ok(::serde_json::from_reader(f)?)
+}
+
+pub fn print_summary(p: &::std::path::Path, verbose: bool, sort_order: SortOrder) -> Result<()> {
+    let v = load(p)?;
+    print_table(&v, verbose, sort_order);
+    Ok(())
+}
+
+/// Print `showbat` table and overall score
+pub fn print_table(v: &[ResultsForStoring], verbose: bool, sort_order: SortOrder) {
+    use ::std::collections::BTreeMap;
+    let mut m: BTreeMap<u64, usize> = BTreeMap::new();
+
+    for (i, entry) in v.iter().enumerate() {
+        m.insert(sort_key(entry, i, sort_order), i);
+    }
+
+    let mut scores: Vec<ScoreEntry> = Vec::with_capacity(40);
+
+    println!(
+        "  kbps  | pktsz || ekbps_^ | loss_^  | delay_^    || ekbps_v | loss_v  | delay_v   || score"
+    );
+    for (_, &i) in m.iter() {
+        let entry = &v[i];
+        let (text, quality_score) = entry.short_summary();
+        println!("{}", text);
+        if verbose {
+            entry.print_to_stdout();
+        }
+
+        scores.push(ScoreEntry::new(&entry.conditions, quality_score));
+    }
+
+    let (overall_score, availability) = overall_score(&scores);
     println!(
         "Overall network score: {:.1}. Availability: {:.0}%",
         overall_score,
-        availability / availability_total * 100.0,
+        availability * 100.0,
     );
-
-    Ok(())
 }
 
 pub fn migrate(p: &::std::path::Path) -> Result<()> {
-    let f = ::std::io::BufReader::new(::std::fs::File::open(p)?);
-    let v: Vec<ResultsForStoring> = ::serde_json::from_reader(f)?;
+    let v = load(p)?;
     ::serde_json::ser::to_writer(&mut ::std::io::stdout().lock(), &v)?;
     Ok(())
 }
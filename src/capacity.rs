@@ -0,0 +1,208 @@
+//! Search for bottleneck rate using a series of short experiments
+
+use crate::experiment::results::ExperimentResults;
+use crate::experiment::statement::{ExperimentDirection, ExperimentInfo};
+use crate::probe::{probe_impl, CmdImpl, CommunicOpts};
+use crate::Result;
+use ::structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct Cmd {
+    #[structopt(flatten)]
+    co: CommunicOpts,
+
+    /// Packet size for probing experiments, in bytes
+    #[structopt(long = "packetsize", default_value = "1200")]
+    packetsize: u32,
+
+    /// Duration of each probing experiment, in milliseconds
+    #[structopt(long = "step-duration", default_value = "2000")]
+    step_duration_ms: u64,
+
+    /// Rate to start ramping up from, kilobits per second
+    #[structopt(long = "min-kbps", default_value = "100")]
+    min_kbps: u32,
+
+    /// Don't try rates above this, kilobits per second
+    #[structopt(long = "max-kbps", default_value = "100000")]
+    max_kbps: u32,
+
+    /// Loss percentage that means the rate is above capacity
+    #[structopt(long = "max-loss", default_value = "2")]
+    max_loss_percent: f32,
+
+    /// Growth of mean delay (compared to the first, slowest experiment), in milliseconds,
+    /// that means the rate is above capacity
+    #[structopt(long = "max-delay-growth", default_value = "50")]
+    max_delay_growth_ms: f32,
+
+    /// Number of bisection steps after ramping up overshoots capacity
+    #[structopt(long = "steps", default_value = "6")]
+    steps: u32,
+
+    /// Direction to measure: send (upload) | recv (download) | both
+    #[structopt(long = "direction", default_value = "both")]
+    direction: ExperimentDirection,
+}
+
+/// Outcome of one experiment in a capacity search
+enum Step {
+    Fits {
+        delay_ms: f32,
+    },
+    Exceeds,
+    /// Experiment did not complete, so nothing is known about the rate
+    Failed,
+}
+
+/// How a capacity search ended
+enum SearchEnd {
+    Bisected,
+    /// Even `--max-kbps` fits
+    MaxKbps,
+    /// An experiment failed, so the search stopped early
+    Failed,
+}
+
+impl Cmd {
+    fn experiment(&self, kbps: u32, direction: ExperimentDirection) -> ExperimentInfo {
+        let effective_ps = (self.packetsize + 32).max(64) as u64;
+        let packetdelay_us = (effective_ps * 8 * 1000 / kbps.max(1) as u64).max(1);
+        let totalpackets = (self.step_duration_ms * 1000 / packetdelay_us).max(50) as u32;
+        ExperimentInfo {
+            packetsize: self.packetsize,
+            packetdelay_us,
+            totalpackets,
+            direction,
+            pacing: Default::default(),
+            rtpmimic: false,
+            profile: None,
+            payload: Default::default(),
+            session_id: 0,
+            pending_start_in_microseconds: 1_000_000,
+        }
+    }
+
+    fn try_rate(
+        &self,
+        kbps: u32,
+        direction: ExperimentDirection,
+        base_delay_ms: Option<f32>,
+    ) -> Result<Step> {
+        let ci = CmdImpl {
+            experiment: self.experiment(kbps, direction),
+            co: self.co.clone(),
+        };
+        let r = match probe_impl(ci) {
+            Ok(r) => r,
+            Err(e) => {
+                eprintln!("{:8} kbps: error: {}", kbps, e);
+                return Ok(Step::Failed);
+            }
+        };
+        let x = match direction {
+            ExperimentDirection::ToServerOnly => r.to_server.as_ref(),
+            _ => r.from_server.as_ref(),
+        };
+        let x: &ExperimentResults = match x {
+            Some(x) => x,
+            None => bail!("No results for {:?} direction", direction),
+        };
+        let loss = x.loss_model.loss_prob * 100.0;
+        let delay_ms = x.delay_model.mean_delay_ms;
+        let growth = delay_ms - base_delay_ms.unwrap_or(delay_ms);
+        let fits = loss <= self.max_loss_percent && growth <= self.max_delay_growth_ms;
+        eprintln!(
+            "{:8} kbps: loss {:5.2}% (send-side {:5.2}%), delay {:6.0}ms (+{:.0}ms) => {}",
+            kbps,
+            loss,
+            x.loss_model.sendside_loss * 100.0,
+            delay_ms,
+            growth,
+            if fits { "fits" } else { "exceeds" },
+        );
+        Ok(if fits {
+            Step::Fits { delay_ms }
+        } else {
+            Step::Exceeds
+        })
+    }
+
+    /// Returns highest rate that fits (None if even the minimal one does not)
+    /// and how the search ended. Failure of the first experiment is an error,
+    /// as there is no rate known to fit.
+    fn search(&self, direction: ExperimentDirection) -> Result<(Option<u32>, SearchEnd)> {
+        let base_delay_ms = match self.try_rate(self.min_kbps, direction, None)? {
+            Step::Fits { delay_ms } => delay_ms,
+            Step::Exceeds => return Ok((None, SearchEnd::Bisected)),
+            Step::Failed => bail!("Experiment at --min-kbps failed"),
+        };
+
+        // Ramp up until overshooting
+        let mut good = self.min_kbps;
+        let mut bad;
+        loop {
+            if good >= self.max_kbps {
+                return Ok((Some(good), SearchEnd::MaxKbps));
+            }
+            let rate = good.saturating_mul(2).min(self.max_kbps);
+            match self.try_rate(rate, direction, Some(base_delay_ms))? {
+                Step::Fits { .. } => good = rate,
+                Step::Exceeds => {
+                    bad = rate;
+                    break;
+                }
+                Step::Failed => return Ok((Some(good), SearchEnd::Failed)),
+            }
+        }
+
+        // Bisect between the last fitting and the first exceeding rate
+        for _ in 0..self.steps {
+            let rate = ((good as f64) * (bad as f64)).sqrt() as u32;
+            if rate <= good || rate >= bad {
+                break;
+            }
+            match self.try_rate(rate, direction, Some(base_delay_ms))? {
+                Step::Fits { .. } => good = rate,
+                Step::Exceeds => bad = rate,
+                Step::Failed => return Ok((Some(good), SearchEnd::Failed)),
+            }
+        }
+        Ok((Some(good), SearchEnd::Bisected))
+    }
+
+    pub fn run(self) -> Result<()> {
+        ensure!(self.min_kbps > 0, "--min-kbps should be positive");
+        ensure!(
+            self.min_kbps <= self.max_kbps,
+            "--min-kbps is above --max-kbps"
+        );
+
+        let mut directions = vec![];
+        if self.direction.client_needs_sender() {
+            directions.push(("Upload", ExperimentDirection::ToServerOnly));
+        }
+        if self.direction.client_needs_receiver() {
+            directions.push(("Download", ExperimentDirection::FromServerOnly));
+        }
+
+        let mut report = vec![];
+        for (name, direction) in directions {
+            eprintln!("Measuring {} capacity", name.to_lowercase());
+            report.push((name, self.search(direction)?));
+        }
+
+        for (name, (kbps, end)) in report {
+            match (kbps, end) {
+                (None, _) => println!("{} capacity: below {} kbps", name, self.min_kbps),
+                (Some(x), SearchEnd::MaxKbps) => println!("{} capacity: at least {} kbps", name, x),
+                (Some(x), SearchEnd::Failed) => println!(
+                    "{} capacity: at least {} kbps (search stopped by a failed experiment)",
+                    name, x
+                ),
+                (Some(x), SearchEnd::Bisected) => println!("{} capacity: {} kbps", name, x),
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,9 +1,12 @@
+use super::pacing::Pacing;
 use super::receiver::Info;
-use super::results::{DelayModel, ExperimentResults, LossModel};
-use super::results::{CLUSTERS, DELAY_DELTAS, DELAY_VALUES, ZERO_DELTA_IDX};
+use super::results::{DelayModel, ExperimentResults, LossModel, TimeSeries};
+use super::results::{
+    CLUSTERS, DELAY_DELTAS, DELAY_VALUES, MAX_INTERVALS, REORDER_EXTENTS, ZERO_DELTA_IDX,
+};
 use crate::Result;
 
-pub fn analyse(v: &[Info], total: usize) -> ExperimentResults {
+pub fn analyse(v: &[Info], total: usize, pacing: Pacing) -> ExperimentResults {
     // Step 1: Sort, deduplicate byt seqn and get delays of input values
     let mut tmp: Vec<(u32, i32)> = Vec::with_capacity(v.len());
     let mut mindelay_ms = ::std::i32::MAX;
@@ -34,6 +37,8 @@ pub fn analyse(v: &[Info], total: usize) -> ExperimentResults {
     r.delay_model.delta_loss1[ZERO_DELTA_IDX] = NONZERO_BUT_SMALL;
     r.delay_model.delta_loss2_20[ZERO_DELTA_IDX] = NONZERO_BUT_SMALL;
     r.delay_model.delta_lossmany[ZERO_DELTA_IDX] = NONZERO_BUT_SMALL;
+    r.reorder_model.extent[0] = NONZERO_BUT_SMALL;
+    r.reorder_model.distance[0] = NONZERO_BUT_SMALL;
 
     //use ::rand::{FromEntropy,rngs::SmallRng};
     //let mut rnd = SmallRng::from_entropy();
@@ -76,6 +81,7 @@ pub fn analyse(v: &[Info], total: usize) -> ExperimentResults {
     let mut prevdelay = 0;
     for (seqn, d) in tmp.iter() {
         let jump_in_seqns = seqn - prev_seqn;
+        let burst_continues = pacing.same_burst(prev_seqn, *seqn);
         if jump_in_seqns <= 1 {
             nonloss_in_a_row += 1;
         } else {
@@ -105,6 +111,8 @@ pub fn analyse(v: &[Info], total: usize) -> ExperimentResults {
 
         let delay_jump = (*d - prevdelay) as i32;
         match jump_in_seqns {
+            // Queue got drained during scheduled silence, not a latch-up or recovery
+            _ if !burst_continues => (),
             0..=1 => {
                 register(delay_jump, &mut r.delay_model.delta_noloss, &DELAY_DELTAS);
             }
@@ -135,6 +143,56 @@ pub fn analyse(v: &[Info], total: usize) -> ExperimentResults {
         r.loss_model.end_lp = last_loss_cluster;
     }
 
+    // Step 4.5: reordering as in RFC 4737 and interarrival jitter as in RFC 3550,
+    // based on arrival order. Duplicates and out-of-range sequence numbers are not counted.
+    // Duplicates are accounted separately.
+    let mut first_arrival_us: Vec<Option<u32>> = vec![None; total];
+    let mut sent_us: Vec<u32> = vec![0; total];
+    let mut prev_arrival: Option<(u32, u32)> = None;
+    let mut jitter_us = 0.0;
+    let mut duplicates_lag_sum_ms = 0.0;
+    let mut next_expected = 0;
+    // Sequence numbers that were maximal at time of arrival, with their arrival positions
+    let mut maxima: Vec<(u32, u32)> = Vec::new();
+    let mut position = 0;
+    let mut reordered = 0;
+    for Info { seqn, st_us, rt_us } in v {
+        let seqn = *seqn;
+        if seqn as usize >= total {
+            continue;
+        }
+        if let Some(orig_rt_us) = first_arrival_us[seqn as usize] {
+            r.loss_model.duplicates += 1;
+            duplicates_lag_sum_ms += rt_us.saturating_sub(orig_rt_us) as f32 / 1000.0;
+            continue;
+        }
+        first_arrival_us[seqn as usize] = Some(*rt_us);
+        sent_us[seqn as usize] = *st_us;
+        if let Some((prev_st_us, prev_rt_us)) = prev_arrival {
+            let d = (*rt_us as f64 - prev_rt_us as f64) - (*st_us as f64 - prev_st_us as f64);
+            jitter_us += (d.abs() - jitter_us) / 16.0;
+        }
+        prev_arrival = Some((*st_us, *rt_us));
+        if seqn >= next_expected {
+            next_expected = seqn + 1;
+            maxima.push((seqn, position));
+        } else {
+            reordered += 1;
+            // The earliest arrived packet with bigger sequence number
+            let i = maxima.partition_point(|(m, _)| *m < seqn);
+            let extent = position - maxima[i].1;
+            let distance = next_expected - 1 - seqn;
+            register(extent as i32, &mut r.reorder_model.extent, &REORDER_EXTENTS);
+            register(
+                distance as i32,
+                &mut r.reorder_model.distance,
+                &REORDER_EXTENTS,
+            );
+            r.reorder_model.max_extent = r.reorder_model.max_extent.max(extent);
+        }
+        position += 1;
+    }
+
     // Step 5: Normalize results
     fn normalize(v: &mut [f32]) {
         let mut sum = 0.0;
@@ -155,7 +213,57 @@ pub fn analyse(v: &[Info], total: usize) -> ExperimentResults {
     normalize(&mut r.delay_model.delta_loss1);
     normalize(&mut r.delay_model.delta_loss2_20);
     normalize(&mut r.delay_model.delta_lossmany);
+    normalize(&mut r.reorder_model.extent);
+    normalize(&mut r.reorder_model.distance);
     r.total_received_packets = tmp.len() as u32;
+    if r.loss_model.duplicates > 0 {
+        r.loss_model.duplicate_prob = r.loss_model.duplicates as f32 / v.len() as f32;
+        r.loss_model.duplicate_mean_lag_ms = duplicates_lag_sum_ms / r.loss_model.duplicates as f32;
+    }
+    r.reorder_model.reorder_prob = if position > 0 {
+        reordered as f32 / position as f32
+    } else {
+        0.0
+    };
+    r.delay_model.jitter_ms = (jitter_us / 1000.0) as f32;
+
+    // Step 6: delay percentiles
+    let mut delays: Vec<i32> = tmp.iter().map(|(_seqn, d)| *d).collect();
+    delays.sort_unstable();
+    let percentile = |p: f32| -> f32 {
+        if delays.is_empty() {
+            return 9999.0;
+        }
+        let rank = (p * delays.len() as f32).ceil() as usize;
+        delays[rank.clamp(1, delays.len()) - 1] as f32
+    };
+    r.delay_model.min_ms = percentile(0.0);
+    r.delay_model.p50_ms = percentile(0.50);
+    r.delay_model.p90_ms = percentile(0.90);
+    r.delay_model.p99_ms = percentile(0.99);
+    r.delay_model.max_ms = percentile(1.0);
+
+    // Step 7: time series
+    let pkts: Vec<(u32, u32, i32)> = tmp
+        .iter()
+        .filter(|(seqn, _d)| (*seqn as usize) < total)
+        .map(|(seqn, d)| (*seqn, sent_us[*seqn as usize], *d))
+        .collect();
+    r.time_series = time_series(&pkts, total as u32);
+
+    // Step 8: two-state loss models, between the first and the last received packets
+    let received: Vec<usize> = (0..total)
+        .filter(|&i| first_arrival_us[i].is_some())
+        .collect();
+    if let (Some(&first), Some(&last)) = (received.first(), received.last()) {
+        let lost: Vec<bool> = first_arrival_us[first..=last]
+            .iter()
+            .map(|x| x.is_none())
+            .collect();
+        r.loss_model.gilbert = super::gilbert::fit_gilbert(&lost);
+        r.loss_model.gilbert_elliott = super::gilbert::fit_gilbert_elliott(&lost);
+    }
+
     r.loss_model.loss_prob = 1.0 - tmp.len() as f32 / total as f32;
     r.delay_model.mean_delay_ms = if tmp.len() > 0 {
         delaysum / tmp.len() as f32
@@ -165,6 +273,70 @@ pub fn analyse(v: &[Info], total: usize) -> ExperimentResults {
     r
 }
 
+/// Split experiment into intervals by sending time.
+/// `pkts` are (seqn, sending time, delay) of received packets, sorted by seqn.
+/// Sending time of lost packets is interpolated from their neighbours.
+fn time_series(pkts: &[(u32, u32, i32)], total: u32) -> TimeSeries {
+    let mut ts = TimeSeries::default();
+    if pkts.is_empty() {
+        return ts;
+    }
+    let (first_seqn, first_st_us, _) = pkts[0];
+    let (last_seqn, last_st_us, _) = pkts[pkts.len() - 1];
+    let spacing_us = if last_seqn > 0 {
+        last_st_us as f64 / last_seqn as f64
+    } else {
+        0.0
+    };
+    let duration_ms = (last_st_us as f64 + spacing_us * (total - 1 - last_seqn) as f64) / 1000.0;
+
+    const INTERVALS_MS: [u32; 8] = [100, 200, 500, 1000, 2000, 5000, 10000, 60000];
+    let interval_ms = *INTERVALS_MS
+        .iter()
+        .find(|&&x| duration_ms / (x as f64) < MAX_INTERVALS as f64)
+        .unwrap_or(&INTERVALS_MS[INTERVALS_MS.len() - 1]);
+    ts.interval_ms = interval_ms;
+    let n = (duration_ms / interval_ms as f64) as usize + 1;
+    let bucket = |st_us: f64| ((st_us.max(0.0) / 1000.0 / interval_ms as f64) as usize).min(n - 1);
+
+    ts.received = vec![0; n];
+    ts.lost = vec![0; n];
+    ts.min_delay_ms = vec![None; n];
+    ts.mean_delay_ms = vec![None; n];
+    ts.max_delay_ms = vec![None; n];
+    let mut delaysums = vec![0i64; n];
+
+    for k in 0..first_seqn {
+        ts.lost[bucket(first_st_us as f64 - spacing_us * (first_seqn - k) as f64)] += 1;
+    }
+    for w in pkts.windows(2) {
+        let (a, a_st_us, _) = w[0];
+        let (b, b_st_us, _) = w[1];
+        for k in a + 1..b {
+            let t = a_st_us as f64
+                + (b_st_us as f64 - a_st_us as f64) * (k - a) as f64 / (b - a) as f64;
+            ts.lost[bucket(t)] += 1;
+        }
+    }
+    for k in last_seqn + 1..total {
+        ts.lost[bucket(last_st_us as f64 + spacing_us * (k - last_seqn) as f64)] += 1;
+    }
+
+    for &(_seqn, st_us, d) in pkts {
+        let i = bucket(st_us as f64);
+        ts.received[i] += 1;
+        delaysums[i] += d as i64;
+        ts.min_delay_ms[i] = Some(ts.min_delay_ms[i].map_or(d, |x| x.min(d)));
+        ts.max_delay_ms[i] = Some(ts.max_delay_ms[i].map_or(d, |x| x.max(d)));
+    }
+    for ((mean, sum), cnt) in ts.mean_delay_ms.iter_mut().zip(delaysums).zip(&ts.received) {
+        if *cnt > 0 {
+            *mean = Some((sum / *cnt as i64) as i32);
+        }
+    }
+    ts
+}
+
 /// Summary for one-sided experiment, based on delay and loss
 struct _Summary {}
 
@@ -197,19 +369,121 @@ impl ExperimentResults {
 }
 
 pub fn read_and_analyse(p: &::std::path::Path) -> Result<()> {
-    let mut f = ::std::io::BufReader::new(::std::fs::File::open(p)?);
-    let totpkt: usize = ::bincode::deserialize_from(&mut f)?;
-    let v: Vec<Info> = ::bincode::deserialize_from(f)?;
+    let d = super::rawfile::load(p)?;
 
-    let r = analyse(&v, totpkt);
+    // Old format files don't record experiment parameters, assume constant pacing
+    let pacing = match d.header {
+        Some(ref h) => {
+            println!(
+                "Recorded by {:?} ({:?}), netmeasure2 {}",
+                h.role, h.direction, h.tool_version
+            );
+            h.experiment.pacing
+        }
+        None => Pacing::Constant,
+    };
+    let r = analyse(&d.v, d.total_packets, pacing);
+    print_analysis(&r);
+    Ok(())
+}
 
+/// Print everything about one-sided results obtained from raw data
+pub fn print_analysis(r: &ExperimentResults) {
     println!(
         "Total received packets: {} (loss {:3.2}%)",
         r.total_received_packets,
         r.loss_model.loss_prob * 100.0,
     );
+    r.visualise_anomalies();
     r.visualise_loss();
+    r.visualise_loss_models();
     println!();
     r.visualise_delay();
-    Ok(())
+    println!();
+    r.visualise_reorder();
+    println!();
+    r.visualise_timeline();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Packets sent every 10 ms with given delays in ms, received in the listed order
+    fn trace(order: &[u32], delays_ms: &[u32]) -> Vec<Info> {
+        order
+            .iter()
+            .map(|&seqn| {
+                let st_us = seqn * 10_000;
+                Info {
+                    seqn,
+                    st_us,
+                    rt_us: st_us + delays_ms[seqn as usize] * 1000,
+                }
+            })
+            .collect()
+    }
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-4, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn jitter_rfc3550() {
+        // Transit time differences are 2, 3 and 0 ms:
+        // J = 2000/16 = 125; J += (3000 - J)/16 = 304.6875; J += (0 - J)/16 = 285.64453125
+        let v = trace(&[0, 1, 2, 3], &[5, 7, 4, 4]);
+        let r = analyse(&v, 4, Pacing::Constant);
+        assert_close(r.delay_model.jitter_ms, 0.285_644_53);
+    }
+
+    #[test]
+    fn reordering_rfc4737() {
+        // Example of RFC 4737 section 4.2.3, from 0: packet 3 arrives after 4 later ones
+        let order = [0, 1, 2, 4, 5, 6, 7, 3, 8];
+        let v = trace(&order, &[1; 9]);
+        let r = analyse(&v, 9, Pacing::Constant);
+        let m = &r.reorder_model;
+        assert_close(m.reorder_prob, 1.0 / 9.0);
+        assert_eq!(m.max_extent, 4);
+        assert_eq!(REORDER_EXTENTS[3], 4);
+        assert!(m.extent[3] > 0.99);
+        // Highest sequence number before packet 3 arrived was 7
+        assert!(m.distance[3] > 0.99);
+        assert_close(r.loss_model.loss_prob, 0.0);
+    }
+
+    #[test]
+    fn in_order_is_not_reordered() {
+        let v = trace(&[0, 1, 3, 4], &[1; 5]);
+        let r = analyse(&v, 5, Pacing::Constant);
+        assert_close(r.reorder_model.reorder_prob, 0.0);
+        assert_eq!(r.reorder_model.max_extent, 0);
+        assert_close(r.loss_model.loss_prob, 0.2);
+    }
+
+    #[test]
+    fn duplicates() {
+        let v = trace(&[0, 1, 1, 2], &[1; 3]);
+        let r = analyse(&v, 3, Pacing::Constant);
+        assert_eq!(r.loss_model.duplicates, 1);
+        assert_close(r.loss_model.duplicate_prob, 0.25);
+        assert_eq!(r.total_received_packets, 3);
+        assert_close(r.loss_model.loss_prob, 0.0);
+    }
+
+    #[test]
+    fn delay_percentiles() {
+        // Delays are a permutation of 1..=100 ms, nearest-rank percentiles
+        let delays: Vec<u32> = (0..100).map(|i| i * 37 % 100 + 1).collect();
+        let order: Vec<u32> = (0..100).collect();
+        let r = analyse(&trace(&order, &delays), 100, Pacing::Constant);
+        let m = &r.delay_model;
+        assert_close(m.min_ms, 1.0);
+        assert_close(m.p50_ms, 50.0);
+        assert_close(m.p90_ms, 90.0);
+        assert_close(m.p99_ms, 99.0);
+        assert_close(m.max_ms, 100.0);
+        assert_close(m.mean_delay_ms, 50.5);
+    }
 }
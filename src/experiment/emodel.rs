@@ -0,0 +1,135 @@
+//! Simplified ITU-T G.107 E-model voice quality estimate
+
+use super::results::{ExperimentResults, LossModel, CLUSTERS};
+
+/// R0 - Is with default parameters
+const R_DEFAULT: f32 = 93.2;
+/// Equipment impairment factor of G.711
+const IE: f32 = 0.0;
+/// Packet-loss robustness factor of G.711 with packet loss concealment
+const BPL: f32 = 25.1;
+/// Packetisation and jitter buffer delay added to the network one
+const CODEC_DELAY_MS: f32 = 10.0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct VoiceQuality {
+    pub r_factor: f32,
+    pub mos: f32,
+}
+
+/// Delay impairment `Id` for one-way mouth-to-ear delay
+fn delay_impairment(ta_ms: f32) -> f32 {
+    let mut id = 0.024 * ta_ms;
+    if ta_ms > 177.3 {
+        id += 0.11 * (ta_ms - 177.3);
+    }
+    id
+}
+
+/// Effective equipment impairment `Ie-eff`. `loss` is from 0 to 1.
+fn loss_impairment(loss: f32, burst_ratio: f32) -> f32 {
+    let ppl = loss * 100.0;
+    IE + (95.0 - IE) * ppl / (ppl / burst_ratio + BPL)
+}
+
+pub fn r_to_mos(r: f32) -> f32 {
+    match r {
+        x if x <= 0.0 => 1.0,
+        x if x >= 100.0 => 4.5,
+        x => 1.0 + 0.035 * x + x * (x - 60.0) * (100.0 - x) * 7.0e-6,
+    }
+}
+
+impl LossModel {
+    /// Average length of loss clusters, in packets
+    pub fn mean_loss_burst(&self) -> f32 {
+        let n: f32 = self.loss.iter().sum();
+        if n <= 0.0 {
+            return 1.0;
+        }
+        let s: f32 = self
+            .loss
+            .iter()
+            .zip(CLUSTERS.iter())
+            .map(|(x, &len)| x * len as f32)
+            .sum();
+        (s / n).max(1.0)
+    }
+}
+
+impl ExperimentResults {
+    /// E-model estimate. `base_delay_ms` is one-way delay of the fastest packet,
+    /// as results only record delays relative to it.
+    pub fn voice_quality(&self, base_delay_ms: f32) -> VoiceQuality {
+        let loss = self.loss_model.loss_prob.clamp(0.0, 1.0);
+
+        // BurstR of a two-state Markov loss model: 1 for random loss, more for bursty one
+        let burst_ratio = if loss > 0.0 && loss < 1.0 {
+            let q = 1.0 / self.loss_model.mean_loss_burst();
+            let p = q * loss / (1.0 - loss);
+            (1.0 / (p + q)).max(1.0)
+        } else {
+            1.0
+        };
+
+        let dm = &self.delay_model;
+        let ta_ms = base_delay_ms + dm.mean_delay_ms + 2.0 * dm.jitter_ms + CODEC_DELAY_MS;
+
+        let r_factor = R_DEFAULT - delay_impairment(ta_ms) - loss_impairment(loss, burst_ratio);
+        VoiceQuality {
+            r_factor,
+            mos: r_to_mos(r_factor),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-3, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn mos_of_r() {
+        assert_close(r_to_mos(-5.0), 1.0);
+        assert_close(r_to_mos(0.0), 1.0);
+        assert_close(r_to_mos(50.0), 2.575);
+        // Default R of G.107 gives the well-known 4.41
+        assert_close(r_to_mos(93.2), 4.409);
+        assert_close(r_to_mos(100.0), 4.5);
+        assert_close(r_to_mos(120.0), 4.5);
+    }
+
+    #[test]
+    fn impairments() {
+        assert_close(delay_impairment(100.0), 2.4);
+        assert_close(delay_impairment(200.0), 7.297);
+        assert_close(loss_impairment(0.0, 1.0), 0.0);
+        // 1% random loss with G.711 PLC: 95 * 1 / (1 + 25.1)
+        assert_close(loss_impairment(0.01, 1.0), 3.6398);
+    }
+
+    #[test]
+    fn perfect_network() {
+        // Only codec delay: R = 93.2 - 0.024 * 10
+        let q = ExperimentResults::default().voice_quality(0.0);
+        assert_close(q.r_factor, 92.96);
+        assert_close(q.mos, 4.4046);
+    }
+
+    #[test]
+    fn bursty_loss() {
+        let mut r = ExperimentResults::default();
+        r.loss_model.loss_prob = 0.02;
+        // All loss clusters are 2 packets long: BurstR = 1 / (0.5 + 0.5 * 0.02 / 0.98) = 1.96
+        r.loss_model.loss[1] = 1.0;
+        r.delay_model.mean_delay_ms = 5.0;
+        r.delay_model.jitter_ms = 1.0;
+        // Ta = 20 + 5 + 2 * 1 + 10 = 37 ms; Ie-eff = 95 * 2 / (2 / 1.96 + 25.1)
+        let q = r.voice_quality(20.0);
+        assert_close(q.r_factor, 85.038);
+        assert_close(q.mos, 4.1993);
+    }
+}
@@ -0,0 +1,272 @@
+//! Generative model of a network path, built from histograms of `ExperimentResults`.
+//!
+//! Loss is semi-Markov: runs of received and lost packets alternate,
+//! with lengths drawn from the measured cluster histograms.
+//! Delay is a random walk: jumps are drawn from the measured delta histogram
+//! matching the number of packets lost just before, and are accepted
+//! (Metropolis-style) so that delay values follow the measured distribution:
+//! percentiles if results have them, value popularity histogram otherwise.
+
+use super::receiver::Info;
+use super::results::{DelayModel, ExperimentResults, CLUSTERS, DELAY_DELTAS, DELAY_VALUES};
+use super::statement::ExperimentInfo;
+use ::rand::{Rng, SeedableRng};
+use ::rand_xorshift::XorShiftRng;
+
+pub struct TraceGenerator {
+    r: ExperimentResults,
+    rng: XorShiftRng,
+    in_loss: bool,
+    remaining_in_run: u32,
+    lost_before: u32,
+    delay_ms: i32,
+}
+
+fn sample_index(weights: &[f32], rng: &mut XorShiftRng) -> usize {
+    let total: f32 = weights.iter().sum();
+    let mut x = rng.gen::<f32>() * total;
+    for (i, &w) in weights.iter().enumerate() {
+        if x < w {
+            return i;
+        }
+        x -= w;
+    }
+    weights.len() - 1
+}
+
+/// Value within a histogram bucket: between the previous bucket's value and this one's
+fn sample_bucket(registry: &[i32], i: usize, rng: &mut XorShiftRng) -> i32 {
+    let hi = if i + 1 == registry.len() && i > 0 {
+        // Last bucket means "more than that"
+        registry[i - 1] * 2
+    } else {
+        registry[i]
+    };
+    let lo = if i == 0 { hi } else { registry[i - 1] + 1 };
+    if lo >= hi {
+        hi
+    } else {
+        rng.gen_range(lo, hi + 1)
+    }
+}
+
+/// Delay jump within a delta histogram bucket: between the neighbouring bucket closer to zero and this one
+fn sample_delta(i: usize, rng: &mut XorShiftRng) -> i32 {
+    let d = DELAY_DELTAS[i];
+    match d {
+        0 => 0,
+        d if d > 0 => rng.gen_range(DELAY_DELTAS[i - 1] + 1, d + 1),
+        d => rng.gen_range(d, DELAY_DELTAS[i + 1]),
+    }
+}
+
+/// Histogram value at `x`, interpolated the same way `analyser::analyse` spreads it
+fn interpolate(hist: &[f32], registry: &[i32], x: i32) -> f32 {
+    let i = registry.partition_point(|&v| v < x);
+    if i >= registry.len() {
+        return hist[registry.len() - 1];
+    }
+    if i == 0 || registry[i] == x {
+        return hist[i];
+    }
+    let (prev, next) = (registry[i - 1], registry[i]);
+    let q = (next - x) as f32 / (next - prev) as f32;
+    hist[i - 1] * q + hist[i] * (1.0 - q)
+}
+
+fn has_percentiles(dm: &DelayModel) -> bool {
+    dm.max_ms > 0.0 && dm.max_ms < 9999.0
+}
+
+/// Relative likelihood of delay value `x`
+fn delay_density(dm: &DelayModel, x: i32) -> f32 {
+    if !has_percentiles(dm) {
+        return interpolate(&dm.value_popularity, &DELAY_VALUES, x);
+    }
+    // Piecewise-uniform between known percentiles
+    let knots = [
+        (dm.min_ms, 0.0),
+        (dm.p50_ms, 0.5),
+        (dm.p90_ms, 0.9),
+        (dm.p99_ms, 0.99),
+        (dm.max_ms, 1.0),
+    ];
+    let x = x as f32;
+    let mut density = 0.0;
+    for w in knots.windows(2) {
+        let ((lo, q_lo), (hi, q_hi)) = (w[0], w[1]);
+        if x >= lo && x <= hi {
+            density += (q_hi - q_lo) / (hi - lo + 1.0);
+        }
+    }
+    density
+}
+
+impl TraceGenerator {
+    pub fn new(r: &ExperimentResults, seed: u64) -> Self {
+        let mut rng = XorShiftRng::seed_from_u64(seed);
+        let delay_ms = if has_percentiles(&r.delay_model) {
+            r.delay_model.p50_ms as i32
+        } else {
+            let i = sample_index(&r.delay_model.value_popularity, &mut rng);
+            sample_bucket(&DELAY_VALUES, i, &mut rng)
+        };
+        let mut g = TraceGenerator {
+            r: r.clone(),
+            rng,
+            in_loss: true,
+            remaining_in_run: 0,
+            lost_before: 0,
+            delay_ms,
+        };
+        g.start_run();
+        g
+    }
+
+    fn start_run(&mut self) {
+        self.in_loss = !self.in_loss;
+        let hist = if self.in_loss {
+            &self.r.loss_model.loss
+        } else {
+            &self.r.loss_model.nonloss
+        };
+        let i = sample_index(hist, &mut self.rng);
+        self.remaining_in_run = sample_bucket(&CLUSTERS, i, &mut self.rng).max(1) as u32;
+    }
+
+    /// Delay of the next packet relative to the fastest one, milliseconds. None if it is lost.
+    pub fn next_packet(&mut self) -> Option<i32> {
+        while self.remaining_in_run == 0 {
+            self.start_run();
+        }
+        self.remaining_in_run -= 1;
+        if self.in_loss {
+            self.lost_before += 1;
+            return None;
+        }
+
+        let dm = &self.r.delay_model;
+        let deltas = match self.lost_before {
+            0 => &dm.delta_noloss,
+            1 => &dm.delta_loss1,
+            2..=20 => &dm.delta_loss2_20,
+            _ => &dm.delta_lossmany,
+        };
+        self.lost_before = 0;
+        let i = sample_index(deltas, &mut self.rng);
+        let proposed = (self.delay_ms + sample_delta(i, &mut self.rng)).max(0);
+
+        let here = delay_density(dm, self.delay_ms);
+        let there = delay_density(dm, proposed);
+        if here <= 0.0 || self.rng.gen::<f32>() * here < there {
+            self.delay_ms = proposed;
+        }
+        let fastest = if has_percentiles(dm) {
+            dm.min_ms as i32
+        } else {
+            0
+        };
+        Some((self.delay_ms - fastest).max(0))
+    }
+}
+
+/// Raw receive records as if experiment `e` was run over the modelled path,
+/// in arrival order like `PacketReceiver` records them.
+pub fn synthesize(r: &ExperimentResults, e: &ExperimentInfo, seed: u64) -> Vec<Info> {
+    let mut g = TraceGenerator::new(r, seed);
+    let mut rng = XorShiftRng::seed_from_u64(seed.wrapping_add(0x5EED));
+    let lm = &r.loss_model;
+    let mut v = Vec::with_capacity(e.totalpackets as usize);
+    let schedule = e.pacing.schedule(e.packetdelay_us);
+    for (seqn, offset) in (0..e.totalpackets).zip(schedule) {
+        let delay_ms = match g.next_packet() {
+            Some(x) => x,
+            None => continue,
+        };
+        let st_us = offset.as_micros() as u32;
+        // Sub-millisecond part is not modelled
+        let rt_us = st_us + delay_ms as u32 * 1000 + rng.gen_range(0, 1000);
+        v.push(Info { seqn, st_us, rt_us });
+        if lm.duplicate_prob > 0.0 && rng.gen::<f32>() < lm.duplicate_prob {
+            let lag_us = (lm.duplicate_mean_lag_ms * 1000.0) as u32;
+            v.push(Info {
+                seqn,
+                st_us,
+                rt_us: rt_us + rng.gen_range(0, 2 * lag_us + 1),
+            });
+        }
+    }
+    v.sort_by_key(|x| x.rt_us);
+    v
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::experiment::analyser::analyse;
+    use crate::experiment::pacing::Pacing;
+    use crate::experiment::statement::ExperimentDirection;
+
+    const PACKETS: u32 = 20_000;
+
+    fn experiment() -> ExperimentInfo {
+        ExperimentInfo {
+            direction: ExperimentDirection::ToServerOnly,
+            packetdelay_us: 1000,
+            packetsize: 100,
+            pending_start_in_microseconds: 2_000_000,
+            rtpmimic: false,
+            pacing: Pacing::Constant,
+            profile: None,
+            payload: Default::default(),
+            session_id: 0,
+            totalpackets: PACKETS,
+        }
+    }
+
+    /// Results of a path with bursty loss and wandering delay of 0 to 40 ms
+    fn known_results() -> ExperimentResults {
+        let mut rng = XorShiftRng::seed_from_u64(7);
+        let mut v = vec![];
+        let mut delay_ms: i32 = 10;
+        let mut lost_left = 0;
+        for seqn in 0..PACKETS {
+            if lost_left == 0 && rng.gen_bool(0.01) {
+                lost_left = rng.gen_range(1, 5);
+            }
+            if lost_left > 0 {
+                lost_left -= 1;
+                continue;
+            }
+            delay_ms = (delay_ms + rng.gen_range(-2, 3)).clamp(0, 40);
+            let st_us = seqn * 1000;
+            v.push(Info {
+                seqn,
+                st_us,
+                rt_us: st_us + delay_ms as u32 * 1000 + rng.gen_range(0, 1000),
+            });
+        }
+        v.sort_by_key(|x| x.rt_us);
+        analyse(&v, PACKETS as usize, Pacing::Constant)
+    }
+
+    /// Total variation distance of normalized histograms
+    fn distance(a: &[f32], b: &[f32]) -> f32 {
+        a.iter().zip(b).map(|(x, y)| (x - y).abs()).sum::<f32>() / 2.0
+    }
+
+    #[test]
+    fn roundtrip_through_analyser() {
+        let orig = known_results();
+        let v = synthesize(&orig, &experiment(), 1);
+        let synth = analyse(&v, PACKETS as usize, Pacing::Constant);
+
+        let (ol, sl) = (&orig.loss_model, &synth.loss_model);
+        assert!((ol.loss_prob - sl.loss_prob).abs() < 0.005);
+        assert!(distance(&ol.loss, &sl.loss) < 0.1);
+        assert!(distance(&ol.nonloss, &sl.nonloss) < 0.2);
+        let (od, sd) = (&orig.delay_model, &synth.delay_model);
+        assert!(distance(&od.value_popularity, &sd.value_popularity) < 0.2);
+        assert!(distance(&od.delta_noloss, &sd.delta_noloss) < 0.2);
+    }
+}
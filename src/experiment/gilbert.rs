@@ -0,0 +1,402 @@
+//! Two-state Markov loss models, fitted to observed loss sequences.
+//!
+//! Parameters follow `tc netem` conventions: the model is in Good or Bad state,
+//! `p` is probability of going from Good to Bad, `r` from Bad to Good,
+//! `h` is probability of packet getting through in Bad state and `k` in Good state.
+
+use super::results::LossModel;
+
+/// Fitting 4-parameter model is costly, only this many first packets are used for it
+const BAUM_WELCH_MAX_PACKETS: usize = 1_000_000;
+const BAUM_WELCH_ITERATIONS: usize = 30;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct GilbertElliott {
+    pub p: f32,
+    pub r: f32,
+    pub h: f32,
+    pub k: f32,
+}
+
+impl GilbertElliott {
+    /// Average loss probability in stationary state
+    pub fn loss_prob(&self) -> f32 {
+        if self.p + self.r <= 0.0 {
+            return 1.0 - self.k;
+        }
+        let bad = self.p / (self.p + self.r);
+        bad * (1.0 - self.h) + (1.0 - bad) * (1.0 - self.k)
+    }
+
+    /// Arguments for `tc qdisc ... netem`
+    pub fn netem(&self) -> String {
+        format!(
+            "loss gemodel {:.4}% {:.4}% {:.4}% {:.4}%",
+            self.p * 100.0,
+            self.r * 100.0,
+            (1.0 - self.h) * 100.0,
+            (1.0 - self.k) * 100.0,
+        )
+    }
+}
+
+fn clamp01(x: f64) -> f32 {
+    x.clamp(0.0, 1.0) as f32
+}
+
+/// Counts of loss patterns in a sequence (`true` = lost)
+#[derive(Default)]
+struct Patterns {
+    n: u64,
+    lost: u64,
+    /// loss followed by loss
+    l_l: u64,
+    /// loss followed by non-loss
+    l_n: u64,
+    /// loss, non-loss, loss
+    l_n_l: u64,
+}
+
+impl Patterns {
+    fn count(seq: &[bool]) -> Self {
+        let mut c = Patterns::default();
+        for (i, &x) in seq.iter().enumerate() {
+            c.n += 1;
+            if !x {
+                continue;
+            }
+            c.lost += 1;
+            match (seq.get(i + 1), seq.get(i + 2)) {
+                (Some(true), _) => c.l_l += 1,
+                (Some(false), Some(true)) => {
+                    c.l_n += 1;
+                    c.l_n_l += 1;
+                }
+                (Some(false), _) => c.l_n += 1,
+                (None, _) => (),
+            }
+        }
+        c
+    }
+}
+
+/// Simple Gilbert model (every packet is lost in Bad state and none in Good one)
+pub fn fit_simple(seq: &[bool]) -> Option<GilbertElliott> {
+    let c = Patterns::count(seq);
+    let received = c.n - c.lost;
+    if c.lost == 0 || received == 0 {
+        return None;
+    }
+    // Each loss burst is one transition to Bad and one back
+    let bursts = c.l_n.max(1) as f64;
+    Some(GilbertElliott {
+        p: clamp01(bursts / received as f64),
+        r: clamp01(bursts / c.lost as f64),
+        h: 0.0,
+        k: 1.0,
+    })
+}
+
+/// Gilbert model (no loss in Good state) by matching probabilities
+/// of patterns `1`, `11` and `101` (1 = lost).
+pub fn fit_gilbert(seq: &[bool]) -> Option<GilbertElliott> {
+    let c = Patterns::count(seq);
+    if c.lost == 0 || c.l_n == 0 {
+        return fit_simple(seq);
+    }
+    let a = c.lost as f64 / c.n as f64;
+    let b = c.l_l as f64 / (c.l_l + c.l_n) as f64;
+    let c101 = c.l_n_l as f64 / c.l_n as f64;
+
+    // For a given loss probability in Bad state `d`, other parameters follow from `a` and `b`.
+    let params = |d: f64| {
+        let r = 1.0 - b / d;
+        let p = a * r / (d - a);
+        (p, r)
+    };
+    let c_of = |d: f64| {
+        let (p, r) = params(d);
+        let h = 1.0 - d;
+        d * ((1.0 - r) * (1.0 - r) * h + r * p) / ((1.0 - r) * h + r)
+    };
+
+    let mut lo = a.max(b) + 1e-9;
+    let mut hi = 1.0;
+    if lo >= hi {
+        return fit_simple(seq);
+    }
+    let increasing = c_of(hi) > c_of(lo);
+    for _ in 0..60 {
+        let mid = (lo + hi) / 2.0;
+        if (c_of(mid) < c101) == increasing {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    let d = (lo + hi) / 2.0;
+    let (p, r) = params(d);
+    if !(p.is_finite() && r.is_finite()) {
+        return fit_simple(seq);
+    }
+    Some(GilbertElliott {
+        p: clamp01(p),
+        r: clamp01(r),
+        h: clamp01(1.0 - d),
+        k: 1.0,
+    })
+}
+
+/// Full Gilbert-Elliott model by Baum-Welch algorithm, starting from Gilbert model
+pub fn fit_gilbert_elliott(seq: &[bool]) -> Option<GilbertElliott> {
+    let seq = &seq[..seq.len().min(BAUM_WELCH_MAX_PACKETS)];
+    let init = fit_gilbert(seq)?;
+    let n = seq.len();
+    if n < 2 {
+        return Some(init);
+    }
+
+    // Good state is 0, Bad state is 1
+    let mut p = (init.p as f64).clamp(1e-6, 1.0 - 1e-6);
+    let mut r = (init.r as f64).clamp(1e-6, 1.0 - 1e-6);
+    let mut loss_in = [0.01f64.min(1.0 - init.k as f64 + 1e-3), 1.0 - init.h as f64];
+    loss_in[1] = loss_in[1].clamp(loss_in[0] + 1e-3, 1.0);
+
+    let mut alpha = vec![[0f64; 2]; n];
+    let mut scale = vec![0f64; n];
+    for _ in 0..BAUM_WELCH_ITERATIONS {
+        let tr = [[1.0 - p, p], [r, 1.0 - r]];
+        let emit = |state: usize, lost: bool| {
+            if lost {
+                loss_in[state]
+            } else {
+                1.0 - loss_in[state]
+            }
+        };
+
+        // Forward pass, scaled
+        let bad = p / (p + r);
+        let mut a = [(1.0 - bad) * emit(0, seq[0]), bad * emit(1, seq[0])];
+        for t in 0..n {
+            if t > 0 {
+                let prev = alpha[t - 1];
+                for j in 0..2 {
+                    a[j] = (prev[0] * tr[0][j] + prev[1] * tr[1][j]) * emit(j, seq[t]);
+                }
+            }
+            let s = (a[0] + a[1]).max(1e-300);
+            scale[t] = s;
+            alpha[t] = [a[0] / s, a[1] / s];
+        }
+
+        // Backward pass, accumulating expected counts
+        let mut trans = [[0f64; 2]; 2];
+        let mut in_state = [0f64; 2];
+        let mut lost_in_state = [0f64; 2];
+        let mut beta = [1.0f64; 2];
+        for t in (0..n).rev() {
+            let g0 = alpha[t][0] * beta[0];
+            let g1 = alpha[t][1] * beta[1];
+            let gs = (g0 + g1).max(1e-300);
+            for (i, g) in [g0 / gs, g1 / gs].iter().enumerate() {
+                in_state[i] += g;
+                if seq[t] {
+                    lost_in_state[i] += g;
+                }
+            }
+            if t == 0 {
+                break;
+            }
+            let eb = [emit(0, seq[t]) * beta[0], emit(1, seq[t]) * beta[1]];
+            for i in 0..2 {
+                for j in 0..2 {
+                    trans[i][j] += alpha[t - 1][i] * tr[i][j] * eb[j] / scale[t];
+                }
+            }
+            beta = [
+                (tr[0][0] * eb[0] + tr[0][1] * eb[1]) / scale[t],
+                (tr[1][0] * eb[0] + tr[1][1] * eb[1]) / scale[t],
+            ];
+        }
+
+        let new_p = trans[0][1] / (trans[0][0] + trans[0][1]).max(1e-300);
+        let new_r = trans[1][0] / (trans[1][0] + trans[1][1]).max(1e-300);
+        let new_loss = [
+            lost_in_state[0] / in_state[0].max(1e-300),
+            lost_in_state[1] / in_state[1].max(1e-300),
+        ];
+        let change = (new_p - p).abs() + (new_r - r).abs();
+        p = new_p.max(1e-9);
+        r = new_r.max(1e-9);
+        loss_in = new_loss;
+        if change < 1e-7 {
+            break;
+        }
+    }
+
+    // Keep Bad state the lossier one
+    if loss_in[0] > loss_in[1] {
+        ::std::mem::swap(&mut p, &mut r);
+        loss_in.swap(0, 1);
+    }
+    Some(GilbertElliott {
+        p: clamp01(p),
+        r: clamp01(r),
+        h: clamp01(1.0 - loss_in[1]),
+        k: clamp01(1.0 - loss_in[0]),
+    })
+}
+
+impl LossModel {
+    /// Simple Gilbert model from loss cluster histograms, for results without fitted models
+    pub fn gilbert_from_clusters(&self) -> Option<GilbertElliott> {
+        let loss = self.loss_prob as f64;
+        if loss <= 0.0 || loss >= 1.0 {
+            return None;
+        }
+        let r = 1.0 / self.mean_loss_burst() as f64;
+        Some(GilbertElliott {
+            p: clamp01(r * loss / (1.0 - loss)),
+            r: clamp01(r),
+            h: 0.0,
+            k: 1.0,
+        })
+    }
+}
+
+/// Parses `tc netem loss gemodel` arguments as `p:r:1-h:1-k`, in percent
+impl ::std::str::FromStr for GilbertElliott {
+    type Err = ::anyhow::Error;
+
+    fn from_str(s: &str) -> crate::Result<GilbertElliott> {
+        let v: Vec<f32> = s
+            .split(':')
+            .map(|x| x.trim_end_matches('%').parse())
+            .collect::<Result<_, _>>()?;
+        ensure!(
+            v.len() == 4 && v.iter().all(|x| (0.0..=100.0).contains(x)),
+            "Gilbert-Elliott model should be p:r:1-h:1-k, in percent"
+        );
+        Ok(GilbertElliott {
+            p: v[0] / 100.0,
+            r: v[1] / 100.0,
+            h: 1.0 - v[2] / 100.0,
+            k: 1.0 - v[3] / 100.0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::rand::{Rng, SeedableRng};
+    use ::rand_xorshift::XorShiftRng;
+
+    /// Loss sequence produced by the model itself, starting in Good state
+    fn simulate(m: &GilbertElliott, n: usize) -> Vec<bool> {
+        let mut rng = XorShiftRng::seed_from_u64(1);
+        let mut bad = false;
+        (0..n)
+            .map(|_| {
+                let pass = if bad { m.h } else { m.k };
+                let lost = !rng.gen_bool(pass as f64);
+                let switch = if bad { m.r } else { m.p };
+                if rng.gen_bool(switch as f64) {
+                    bad = !bad;
+                }
+                lost
+            })
+            .collect()
+    }
+
+    fn assert_near(a: f32, b: f32, tolerance: f32) {
+        assert!(
+            (a - b).abs() <= tolerance,
+            "{} is not {} +- {}",
+            a,
+            b,
+            tolerance
+        );
+    }
+
+    #[test]
+    fn stationary_loss() {
+        let m = GilbertElliott {
+            p: 0.1,
+            r: 0.4,
+            h: 0.5,
+            k: 1.0,
+        };
+        // Bad state 20% of time, half of packets lost there
+        assert_near(m.loss_prob(), 0.1, 1e-6);
+    }
+
+    #[test]
+    fn parse_and_netem() {
+        let m: GilbertElliott = "10%:40%:50%:0%".parse().unwrap();
+        assert_eq!(
+            m,
+            GilbertElliott {
+                p: 0.1,
+                r: 0.4,
+                h: 0.5,
+                k: 1.0
+            }
+        );
+        assert_eq!(m.netem(), "loss gemodel 10.0000% 40.0000% 50.0000% 0.0000%");
+        assert!("10:40:50".parse::<GilbertElliott>().is_err());
+        assert!("10:40:50:101".parse::<GilbertElliott>().is_err());
+    }
+
+    #[test]
+    fn simple_model_of_periodic_loss() {
+        // 2 lost, then 8 received, 100 times: 100 bursts over 800 received and 200 lost packets
+        let seq: Vec<bool> = (0..1000).map(|i| i % 10 < 2).collect();
+        let m = fit_simple(&seq).unwrap();
+        assert_near(m.p, 0.125, 1e-6);
+        assert_near(m.r, 0.5, 1e-6);
+        assert_eq!((m.h, m.k), (0.0, 1.0));
+    }
+
+    #[test]
+    fn no_loss_no_model() {
+        let seq = vec![false; 100];
+        assert!(fit_simple(&seq).is_none());
+        assert!(fit_gilbert(&seq).is_none());
+        assert!(fit_gilbert_elliott(&seq).is_none());
+    }
+
+    #[test]
+    fn gilbert_fit_recovers_parameters() {
+        let truth = GilbertElliott {
+            p: 0.02,
+            r: 0.3,
+            h: 0.4,
+            k: 1.0,
+        };
+        let seq = simulate(&truth, 200_000);
+        let m = fit_gilbert(&seq).unwrap();
+        assert_near(m.p, truth.p, 0.005);
+        assert_near(m.r, truth.r, 0.05);
+        assert_near(m.h, truth.h, 0.05);
+        assert_eq!(m.k, 1.0);
+        assert_near(m.loss_prob(), truth.loss_prob(), 0.003);
+    }
+
+    #[test]
+    fn gilbert_elliott_fit_recovers_parameters() {
+        let truth = GilbertElliott {
+            p: 0.01,
+            r: 0.2,
+            h: 0.3,
+            k: 0.99,
+        };
+        let seq = simulate(&truth, 200_000);
+        let m = fit_gilbert_elliott(&seq).unwrap();
+        assert_near(m.p, truth.p, 0.003);
+        assert_near(m.r, truth.r, 0.05);
+        assert_near(m.h, truth.h, 0.05);
+        assert_near(m.k, truth.k, 0.003);
+        assert_near(m.loss_prob(), truth.loss_prob(), 0.003);
+    }
+}
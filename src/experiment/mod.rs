@@ -1,4 +1,12 @@
 pub mod analyser;
+pub mod emodel;
+pub mod generator;
+pub mod gilbert;
+pub mod pacing;
+pub mod payload;
+pub mod pcap;
+pub mod profile;
+pub mod rawfile;
 pub mod receiver;
 pub mod results;
 pub mod sender;
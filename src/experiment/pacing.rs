@@ -0,0 +1,245 @@
+use ::rand::{Rng, SeedableRng};
+use ::rand_xorshift::XorShiftRng;
+use ::std::cell::RefCell;
+use ::std::collections::HashMap;
+use ::std::time::Duration;
+
+/// Longest train the sender can emit back-to-back before the tail
+/// is more than 10ms late and gets counted as send-side loss
+pub const MAX_TRAIN_LENGTH: u32 = 100;
+
+/// How sending times of packets are distributed within an experiment
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Copy, Clone, Default)]
+#[serde(try_from = "String", into = "String")]
+pub enum Pacing {
+    /// Packets are evenly spaced by `packetdelay`
+    #[default]
+    Constant,
+
+    /// Exponentially distributed inter-packet delays with `packetdelay` mean
+    Poisson,
+
+    /// `burst` packets spaced by `packetdelay`, then `gap_us` of silence
+    OnOff { burst: u32, gap_us: u64 },
+
+    /// `length` back-to-back packets, trains spaced to keep `packetdelay` mean
+    Train { length: u32 },
+}
+
+impl ::std::str::FromStr for Pacing {
+    type Err = ::anyhow::Error;
+
+    fn from_str(s: &str) -> crate::Result<Pacing> {
+        let mut it = s.split(':');
+        let kind = it.next().unwrap_or("");
+        let args: Vec<u64> = it.map(|x| x.parse()).collect::<Result<_, _>>()?;
+        Ok(match (kind, &args[..]) {
+            ("constant", []) => Pacing::Constant,
+            ("poisson", []) => Pacing::Poisson,
+            ("onoff", [burst, gap_us]) => Pacing::OnOff {
+                burst: *burst as u32,
+                gap_us: *gap_us,
+            },
+            ("train", [length]) => Pacing::Train {
+                length: *length as u32,
+            },
+            _ => bail!(
+                "Pacing should be constant, poisson, onoff:<burst>:<gap_us> or train:<length>"
+            ),
+        })
+    }
+}
+
+impl ::std::fmt::Display for Pacing {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self {
+            Pacing::Constant => write!(f, "constant"),
+            Pacing::Poisson => write!(f, "poisson"),
+            Pacing::OnOff { burst, gap_us } => write!(f, "onoff:{}:{}", burst, gap_us),
+            Pacing::Train { length } => write!(f, "train:{}", length),
+        }
+    }
+}
+
+impl ::std::convert::TryFrom<String> for Pacing {
+    type Error = ::anyhow::Error;
+
+    fn try_from(s: String) -> crate::Result<Pacing> {
+        s.parse()
+    }
+}
+
+impl From<Pacing> for String {
+    fn from(x: Pacing) -> String {
+        x.to_string()
+    }
+}
+
+impl Pacing {
+    /// Scheduled sending times of packets, as offsets from experiment start
+    pub fn schedule(&self, packetdelay_us: u64) -> Schedule {
+        Schedule {
+            pacing: *self,
+            packetdelay_us,
+            seqn: 0,
+            poisson_sum: 0.0,
+            rng: poisson_rng(),
+        }
+    }
+
+    /// Scheduled sending time of `seqn`-th packet. For `seqn` equal to number of packets
+    /// it is the duration of experiment.
+    pub fn offset(&self, seqn: u32, packetdelay_us: u64) -> Duration {
+        let delay = Duration::from_micros(packetdelay_us);
+        match *self {
+            Pacing::Constant => delay * seqn,
+            Pacing::Poisson => delay.mul_f64(poisson_sum(seqn)),
+            Pacing::OnOff { burst, gap_us } => {
+                let burst = burst.max(1);
+                let period = delay * (burst - 1) + Duration::from_micros(gap_us);
+                period * (seqn / burst) + delay * (seqn % burst)
+            }
+            Pacing::Train { length } => {
+                let length = length.max(1);
+                delay * (seqn / length * length)
+            }
+        }
+    }
+
+    /// Whether there is no scheduled silence between sending those two packets.
+    /// Delay changes across such silence are expected, as queues get drained.
+    pub fn same_burst(&self, seqn1: u32, seqn2: u32) -> bool {
+        match *self {
+            Pacing::Constant | Pacing::Poisson => true,
+            Pacing::OnOff { burst: n, .. } | Pacing::Train { length: n } => {
+                n == 0 || seqn1 / n == seqn2 / n
+            }
+        }
+    }
+
+    /// Average time between packets, microseconds
+    pub fn mean_packetdelay_us(&self, packetdelay_us: u64) -> f64 {
+        match *self {
+            Pacing::Constant | Pacing::Poisson | Pacing::Train { .. } => packetdelay_us as f64,
+            Pacing::OnOff { burst, gap_us } => {
+                let burst = burst.max(1) as f64;
+                ((burst - 1.0) * packetdelay_us as f64 + gap_us as f64) / burst
+            }
+        }
+    }
+
+    pub fn check_limits(&self) -> ::std::result::Result<(), &'static str> {
+        match *self {
+            Pacing::Constant | Pacing::Poisson => (),
+            Pacing::OnOff { burst, gap_us } => {
+                if burst == 0 {
+                    return Err("zero burst length");
+                }
+                if gap_us > 60_000_000 {
+                    return Err("burst gap too big");
+                }
+            }
+            Pacing::Train { length } => {
+                if length == 0 {
+                    return Err("zero train length");
+                }
+                if length > MAX_TRAIN_LENGTH {
+                    return Err("train too long");
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn poisson_rng() -> XorShiftRng {
+    // Fixed seed, so both peers (and `ExperimentInfo::duration`) agree on the schedule
+    let seed = [7, 1, 4, 3, 8, 2, 9, 6, 5, 11, 10, 13, 12, 15, 14, 16];
+    SeedableRng::from_seed(seed)
+}
+
+/// Exponentially distributed value with unit mean
+fn exponential(rng: &mut XorShiftRng) -> f64 {
+    let u: f64 = rng.gen();
+    -(1.0 - u).ln()
+}
+
+thread_local! {
+    /// Server checks duration on each retried request, so don't walk the schedule every time
+    static POISSON_SUMS: RefCell<HashMap<u32, f64>> = RefCell::new(HashMap::new());
+}
+
+/// Sum of first `n` inter-packet delays of Poisson schedule, in units of mean delay
+fn poisson_sum(n: u32) -> f64 {
+    if let Some(x) = POISSON_SUMS.with(|c| c.borrow().get(&n).copied()) {
+        return x;
+    }
+    let mut rng = poisson_rng();
+    let mut sum = 0.0;
+    for _ in 0..n {
+        sum += exponential(&mut rng);
+    }
+    POISSON_SUMS.with(|c| {
+        let mut c = c.borrow_mut();
+        if c.len() >= 64 {
+            c.clear();
+        }
+        c.insert(n, sum);
+    });
+    sum
+}
+
+/// Infinite iterator of packet sending times relative to experiment start
+pub struct Schedule {
+    pacing: Pacing,
+    packetdelay_us: u64,
+    seqn: u32,
+    /// Same as `poisson_sum(seqn)`, but computed incrementally
+    poisson_sum: f64,
+    rng: XorShiftRng,
+}
+
+impl Iterator for Schedule {
+    type Item = Duration;
+
+    fn next(&mut self) -> Option<Duration> {
+        let seqn = self.seqn;
+        self.seqn += 1;
+        Some(match self.pacing {
+            Pacing::Poisson => {
+                let t = Duration::from_micros(self.packetdelay_us).mul_f64(self.poisson_sum);
+                self.poisson_sum += exponential(&mut self.rng);
+                t
+            }
+            _ => self.pacing.offset(seqn, self.packetdelay_us),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn train_length_limits() {
+        assert_eq!(
+            Pacing::Train { length: 0 }.check_limits(),
+            Err("zero train length")
+        );
+        assert_eq!(Pacing::Train { length: 1 }.check_limits(), Ok(()));
+        assert_eq!(
+            Pacing::Train {
+                length: MAX_TRAIN_LENGTH
+            }
+            .check_limits(),
+            Ok(())
+        );
+        assert_eq!(
+            Pacing::Train {
+                length: MAX_TRAIN_LENGTH + 1
+            }
+            .check_limits(),
+            Err("train too long")
+        );
+    }
+}
@@ -0,0 +1,187 @@
+//! Content of data packets after the header: filler bytes (session-seeded
+//! pseudo-random ones by default) and a checksum,
+//! so that corrupted or rewritten packets can be detected.
+
+use super::statement::MINPACKETSIZE;
+use ::byteorder::{ByteOrder, BE};
+
+/// What to fill packets with after the header
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Default)]
+#[serde(try_from = "String", into = "String")]
+pub enum PayloadFill {
+    /// Incompressible session-seeded pseudo-random bytes
+    #[default]
+    Random,
+
+    /// Zero bytes, trivially compressible
+    Zeros,
+
+    /// The specified bytes repeated
+    Pattern(Vec<u8>),
+}
+
+impl ::std::str::FromStr for PayloadFill {
+    type Err = ::anyhow::Error;
+
+    fn from_str(s: &str) -> crate::Result<PayloadFill> {
+        Ok(match s {
+            "random" => PayloadFill::Random,
+            "zeros" => PayloadFill::Zeros,
+            _ if s.starts_with("pattern:") => {
+                let hex = &s["pattern:".len()..];
+                ensure!(
+                    !hex.is_empty() && hex.len().is_multiple_of(2),
+                    "Pattern should be even number of hex digits"
+                );
+                let mut v = Vec::with_capacity(hex.len() / 2);
+                for i in (0..hex.len()).step_by(2) {
+                    v.push(u8::from_str_radix(&hex[i..i + 2], 16)?);
+                }
+                PayloadFill::Pattern(v)
+            }
+            _ => bail!("Payload should be random, zeros or pattern:<hex bytes>"),
+        })
+    }
+}
+
+impl ::std::fmt::Display for PayloadFill {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self {
+            PayloadFill::Random => write!(f, "random"),
+            PayloadFill::Zeros => write!(f, "zeros"),
+            PayloadFill::Pattern(v) => {
+                write!(f, "pattern:")?;
+                for b in v {
+                    write!(f, "{:02x}", b)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl ::std::convert::TryFrom<String> for PayloadFill {
+    type Error = ::anyhow::Error;
+
+    fn try_from(s: String) -> crate::Result<PayloadFill> {
+        s.parse()
+    }
+}
+
+impl From<PayloadFill> for String {
+    fn from(x: PayloadFill) -> String {
+        x.to_string()
+    }
+}
+
+impl PayloadFill {
+    pub fn check_limits(&self) -> ::std::result::Result<(), &'static str> {
+        match self {
+            PayloadFill::Pattern(v) if v.is_empty() => Err("empty payload pattern"),
+            PayloadFill::Pattern(v) if v.len() > 256 => Err("payload pattern too long"),
+            _ => Ok(()),
+        }
+    }
+
+    /// Write filler bytes for the packet with the specified sequence number
+    fn write(&self, out: &mut [u8], session_id: u64, seqn: u32) {
+        match self {
+            PayloadFill::Random => {
+                let mut rng = SplitMix64::new(session_id, seqn);
+                for chunk in out.chunks_mut(8) {
+                    let n = chunk.len();
+                    chunk.copy_from_slice(&rng.next().to_le_bytes()[..n]);
+                }
+            }
+            PayloadFill::Zeros => {
+                for b in out.iter_mut() {
+                    *b = 0;
+                }
+            }
+            PayloadFill::Pattern(v) => {
+                for (b, x) in out.iter_mut().zip(v.iter().cycle()) {
+                    *b = *x;
+                }
+            }
+        }
+    }
+
+    /// Check filler bytes of the packet with the specified sequence number
+    fn matches(&self, data: &[u8], session_id: u64, seqn: u32) -> bool {
+        match self {
+            PayloadFill::Random => {
+                let mut rng = SplitMix64::new(session_id, seqn);
+                data.chunks(8)
+                    .all(|chunk| chunk == &rng.next().to_le_bytes()[..chunk.len()])
+            }
+            PayloadFill::Zeros => data.iter().all(|&b| b == 0),
+            PayloadFill::Pattern(v) => data.iter().zip(v.iter().cycle()).all(|(a, b)| a == b),
+        }
+    }
+}
+
+/// Packets at least this big have a checksum in their last bytes
+pub const MIN_CHECKSUMMED_PACKETSIZE: usize = MINPACKETSIZE + 4;
+
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn new(session_id: u64, seqn: u32) -> Self {
+        SplitMix64(session_id ^ (seqn as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+/// FNV-1a
+fn checksum(data: &[u8]) -> u32 {
+    let mut h: u32 = 0x811c_9dc5;
+    for &b in data {
+        h ^= b as u32;
+        h = h.wrapping_mul(0x0100_0193);
+    }
+    h
+}
+
+fn payload_range(len: usize) -> ::std::ops::Range<usize> {
+    if len >= MIN_CHECKSUMMED_PACKETSIZE {
+        MINPACKETSIZE..len - 4
+    } else {
+        MINPACKETSIZE..len
+    }
+}
+
+/// Fill the payload and put the checksum. Header must be already written.
+pub fn fill(pkt: &mut [u8], session_id: u64, seqn: u32, how: &PayloadFill) {
+    let len = pkt.len();
+    how.write(&mut pkt[payload_range(len)], session_id, seqn);
+    if len >= MIN_CHECKSUMMED_PACKETSIZE {
+        let c = checksum(&pkt[..len - 4]);
+        BE::write_u32(&mut pkt[len - 4..], c);
+    }
+}
+
+/// Check only the checksum, for packets of unknown session, e.g. from a capture.
+/// Packets too small to have a checksum don't pass.
+pub fn checksum_ok(pkt: &[u8]) -> bool {
+    let len = pkt.len();
+    len >= MIN_CHECKSUMMED_PACKETSIZE && BE::read_u32(&pkt[len - 4..]) == checksum(&pkt[..len - 4])
+}
+
+/// Check payload and checksum of received packet
+pub fn verify(pkt: &[u8], session_id: u64, how: &PayloadFill) -> bool {
+    let len = pkt.len();
+    if len >= MIN_CHECKSUMMED_PACKETSIZE
+        && BE::read_u32(&pkt[len - 4..]) != checksum(&pkt[..len - 4])
+    {
+        return false;
+    }
+    let seqn = BE::read_u32(&pkt[12..16]);
+    how.matches(&pkt[payload_range(len)], session_id, seqn)
+}
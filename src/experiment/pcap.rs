@@ -0,0 +1,521 @@
+//! Classic libpcap files with netmeasure2 data packets: writing synthetic ones and reading
+//! real captures
+
+use super::pacing::Pacing;
+use super::payload::{self, PayloadFill, MIN_CHECKSUMMED_PACKETSIZE};
+use super::rawfile::{RawData, RawDirection};
+use super::receiver::Info;
+use super::statement::MINPACKETSIZE;
+use crate::Result;
+use ::byteorder::{ByteOrder, BE, LE};
+use ::std::io::{Read, Write};
+use ::std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use ::std::path::Path;
+
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+/// Raw IP packets, without link-layer header
+pub const LINKTYPE_RAW: u32 = 101;
+
+/// Addresses of synthetic packets. Real ones are not saved in raw data files.
+const CLIENT: (Ipv4Addr, u16) = (Ipv4Addr::new(192, 0, 2, 2), 40000);
+const SERVER: (Ipv4Addr, u16) = (Ipv4Addr::new(192, 0, 2, 1), 40001);
+
+const IP_UDP_HEADERS: usize = 20 + 8;
+
+fn ipv4_checksum(hdr: &[u8]) -> u16 {
+    let mut sum: u32 = hdr.chunks(2).map(|x| BE::read_u16(x) as u32).sum();
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Write receive records as packets with timestamps of their reception.
+/// Payload is regenerated from the experiment's fill, so packets carry valid checksums.
+pub fn write_synthetic(out: &mut dyn Write, d: &RawData) -> Result<()> {
+    let mut hdr = [0u8; 24];
+    LE::write_u32(&mut hdr[0..4], PCAP_MAGIC);
+    LE::write_u16(&mut hdr[4..6], 2);
+    LE::write_u16(&mut hdr[6..8], 4);
+    LE::write_u32(&mut hdr[16..20], 65535);
+    LE::write_u32(&mut hdr[20..24], LINKTYPE_RAW);
+    out.write_all(&hdr)?;
+
+    let (src, dst) = match d.header.as_ref().map(|h| h.direction) {
+        Some(RawDirection::FromServer) => (SERVER, CLIENT),
+        _ => (CLIENT, SERVER),
+    };
+    let experiment = d.header.as_ref().map(|h| &h.experiment);
+    let rtpmimic = experiment.is_some_and(|e| e.rtpmimic);
+    let session_id = experiment.map_or(0, |e| e.session_id);
+    let fill = experiment.map_or(PayloadFill::Zeros, |e| e.payload.clone());
+
+    // Place the experiment so that it ends when the file was saved
+    let last_rt_us = d.v.iter().map(|x| x.rt_us as u64).max().unwrap_or(0);
+    let base_us = d
+        .header
+        .as_ref()
+        .map_or(0, |h| (h.saved_at * 1_000_000).saturating_sub(last_rt_us));
+
+    let mut pkt = vec![];
+    for inf in &d.v {
+        let payload_len = match experiment {
+            Some(e) => match e.profile {
+                Some(p) => p.packetsize(inf.seqn).min(e.packetsize) as usize,
+                None => e.packetsize as usize,
+            },
+            None => MIN_CHECKSUMMED_PACKETSIZE,
+        }
+        .max(MINPACKETSIZE);
+        let orig_len = IP_UDP_HEADERS + payload_len;
+
+        pkt.clear();
+        pkt.resize(orig_len, 0);
+        // IPv4
+        pkt[0] = 0x45;
+        BE::write_u16(&mut pkt[2..4], orig_len.min(0xFFFF) as u16);
+        BE::write_u16(&mut pkt[4..6], inf.seqn as u16);
+        pkt[8] = 64;
+        pkt[9] = 17;
+        pkt[12..16].copy_from_slice(&src.0.octets());
+        pkt[16..20].copy_from_slice(&dst.0.octets());
+        let c = ipv4_checksum(&pkt[0..20]);
+        BE::write_u16(&mut pkt[10..12], c);
+        // UDP, without checksum
+        BE::write_u16(&mut pkt[20..22], src.1);
+        BE::write_u16(&mut pkt[22..24], dst.1);
+        BE::write_u16(&mut pkt[24..26], (8 + payload_len).min(0xFFFF) as u16);
+        // netmeasure2 header
+        let h = &mut pkt[IP_UDP_HEADERS..];
+        if rtpmimic {
+            h[0] = 2 << 6;
+            h[1] = 100;
+            BE::write_u16(&mut h[2..4], (inf.seqn & 0xFFFF) as u16);
+            BE::write_u32(&mut h[4..8], inf.st_us * 90 / 1000);
+            BE::write_u32(&mut h[8..12], (session_id & 0xFFFF_FFFF) as u32);
+        }
+        BE::write_u32(&mut h[12..16], inf.seqn);
+        BE::write_u32(&mut h[16..20], inf.st_us);
+        payload::fill(h, session_id, inf.seqn, &fill);
+
+        let ts_us = base_us + inf.rt_us as u64;
+        let mut rec = [0u8; 16];
+        LE::write_u32(&mut rec[0..4], (ts_us / 1_000_000) as u32);
+        LE::write_u32(&mut rec[4..8], (ts_us % 1_000_000) as u32);
+        LE::write_u32(&mut rec[8..12], pkt.len() as u32);
+        LE::write_u32(&mut rec[12..16], orig_len as u32);
+        out.write_all(&rec)?;
+        out.write_all(&pkt)?;
+    }
+    Ok(())
+}
+
+const PCAP_MAGIC_NS: u32 = 0xa1b2_3c4d;
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+/// Largest snapshot length used by libpcap. Bigger records mean a corrupted file.
+const MAX_CAPLEN: usize = 262_144;
+/// Flows whose seqn range is that many times bigger than number of packets are not ours
+const MAX_SEQN_SPREAD: usize = 16;
+/// Number of packets to analyse is limited, as analysis allocates per packet
+const MAX_CAPTURE_TOTAL: usize = 10_000_000;
+
+/// netmeasure2 data packets between two endpoints, as seen in a capture
+pub struct Flow {
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    pub rtpmimic: bool,
+    /// Sequence number, sending timestamp and capture time, in capture order
+    pub packets: Vec<(u32, u32, u64)>,
+}
+
+/// Get IP payload of a link-layer frame, if it is a first (or the only) fragment of UDP datagram
+fn udp_of_frame(linktype: u32, frame: &[u8]) -> Option<(SocketAddr, SocketAddr, &[u8])> {
+    let (ethertype, ip) = match linktype {
+        LINKTYPE_ETHERNET => {
+            let mut off = 12;
+            let mut et = BE::read_u16(frame.get(off..off + 2)?);
+            // 802.1Q and 802.1ad VLAN tags
+            while et == 0x8100 || et == 0x88a8 {
+                off += 4;
+                et = BE::read_u16(frame.get(off..off + 2)?);
+            }
+            (et, frame.get(off + 2..)?)
+        }
+        LINKTYPE_LINUX_SLL => (BE::read_u16(frame.get(14..16)?), frame.get(16..)?),
+        LINKTYPE_NULL => {
+            let family = LE::read_u32(frame.get(0..4)?);
+            let family = if family > 0xFFFF {
+                family.swap_bytes()
+            } else {
+                family
+            };
+            // AF_INET6 differs between systems
+            (if family == 2 { 0x0800 } else { 0x86dd }, frame.get(4..)?)
+        }
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => match frame.first()? >> 4 {
+            4 => (0x0800, frame),
+            6 => (0x86dd, frame),
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    let (src, dst, udp) = match ethertype {
+        0x0800 => {
+            let ihl = ((ip.first()? & 0x0F) as usize) * 4;
+            if ihl < 20 {
+                return None;
+            }
+            let fragment_offset = BE::read_u16(ip.get(6..8)?) & 0x1FFF;
+            if *ip.get(9)? != 17 || fragment_offset != 0 {
+                return None;
+            }
+            let a = ip.get(12..20)?;
+            let src = Ipv4Addr::new(a[0], a[1], a[2], a[3]);
+            let dst = Ipv4Addr::new(a[4], a[5], a[6], a[7]);
+            (IpAddr::V4(src), IpAddr::V4(dst), ip.get(ihl..)?)
+        }
+        0x86dd => {
+            // Extension headers are not supported
+            if *ip.get(6)? != 17 {
+                return None;
+            }
+            let mut src = [0u8; 16];
+            let mut dst = [0u8; 16];
+            src.copy_from_slice(ip.get(8..24)?);
+            dst.copy_from_slice(ip.get(24..40)?);
+            (
+                IpAddr::V6(Ipv6Addr::from(src)),
+                IpAddr::V6(Ipv6Addr::from(dst)),
+                ip.get(40..)?,
+            )
+        }
+        _ => return None,
+    };
+    let sport = BE::read_u16(udp.get(0..2)?);
+    let dport = BE::read_u16(udp.get(2..4)?);
+    // Ethernet padding and FCS may follow the datagram.
+    // Captured part may be shorter than the datagram, it is returned as is.
+    let len = BE::read_u16(udp.get(4..6)?) as usize;
+    if len < 8 {
+        return None;
+    }
+    Some((
+        SocketAddr::new(src, sport),
+        SocketAddr::new(dst, dport),
+        udp.get(8..len.min(udp.len()))?,
+    ))
+}
+
+/// Extract netmeasure2 data packets from classic libpcap file, grouped by flow
+pub fn read_flows(p: &Path) -> Result<Vec<Flow>> {
+    let mut f = ::std::io::BufReader::new(::std::fs::File::open(p)?);
+    let mut hdr = [0u8; 24];
+    f.read_exact(&mut hdr)?;
+    let (big_endian, nanos) = match (LE::read_u32(&hdr[0..4]), BE::read_u32(&hdr[0..4])) {
+        (PCAP_MAGIC, _) => (false, false),
+        (PCAP_MAGIC_NS, _) => (false, true),
+        (_, PCAP_MAGIC) => (true, false),
+        (_, PCAP_MAGIC_NS) => (true, true),
+        _ => bail!("Not a libpcap file (pcapng is not supported, convert it with editcap -F pcap)"),
+    };
+    let rd32 = |x: &[u8]| {
+        if big_endian {
+            BE::read_u32(x)
+        } else {
+            LE::read_u32(x)
+        }
+    };
+    let linktype = rd32(&hdr[20..24]) & 0xFFFF;
+
+    let mut flows: Vec<Flow> = vec![];
+    let mut index = ::std::collections::HashMap::<(SocketAddr, SocketAddr, bool), usize>::new();
+    let mut rec = [0u8; 16];
+    let mut frame = vec![];
+    let mut truncated_or_bad = 0usize;
+    loop {
+        match f.read_exact(&mut rec) {
+            Ok(()) => (),
+            Err(ref e) if e.kind() == ::std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => Err(e)?,
+        }
+        let ts_us = rd32(&rec[0..4]) as u64 * 1_000_000
+            + if nanos {
+                rd32(&rec[4..8]) as u64 / 1000
+            } else {
+                rd32(&rec[4..8]) as u64
+            };
+        let caplen = rd32(&rec[8..12]) as usize;
+        if caplen > MAX_CAPLEN {
+            bail!("Corrupted capture: record of {} bytes", caplen);
+        }
+        frame.resize(caplen, 0);
+        f.read_exact(&mut frame)?;
+
+        let (src, dst, payload) = match udp_of_frame(linktype, &frame) {
+            Some(x) => x,
+            None => continue,
+        };
+        // Packets truncated by snapshot length can't be verified
+        if !payload::checksum_ok(payload) {
+            truncated_or_bad += 1;
+            continue;
+        }
+        let rtpmimic = match payload {
+            [0, 0, 0, ..] => false,
+            [0x80, 0x64, ..] => true,
+            _ => continue,
+        };
+        let seqn = BE::read_u32(&payload[12..16]);
+        let st_us = BE::read_u32(&payload[16..20]);
+
+        let i = *index.entry((src, dst, rtpmimic)).or_insert_with(|| {
+            flows.push(Flow {
+                src,
+                dst,
+                rtpmimic,
+                packets: vec![],
+            });
+            flows.len() - 1
+        });
+        flows[i].packets.push((seqn, st_us, ts_us));
+    }
+    if truncated_or_bad > 0 && flows.is_empty() {
+        eprintln!(
+            "Skipped {} UDP packets without valid netmeasure2 checksum. Capture with full snapshot length (tcpdump -s 0).",
+            truncated_or_bad
+        );
+    }
+    Ok(flows)
+}
+
+impl Flow {
+    /// Convert to receive records. Clocks of sender and capturing host are not synchronised,
+    /// so times are shifted to make the smallest delay zero.
+    pub fn to_infos(&self) -> Vec<Info> {
+        let base_us = self
+            .packets
+            .iter()
+            .map(|&(_, st_us, ts_us)| ts_us as i64 - st_us as i64)
+            .min()
+            .unwrap_or(0);
+        self.packets
+            .iter()
+            .map(|&(seqn, st_us, ts_us)| Info {
+                seqn,
+                st_us,
+                rt_us: (ts_us as i64 - base_us) as u32,
+            })
+            .collect()
+    }
+}
+
+/// Analyse each flow of netmeasure2 data packets found in a capture
+pub fn analyse_capture(p: &Path, min_packets: usize, pacing: Pacing) -> Result<()> {
+    let flows = read_flows(p)?;
+    let mut found = false;
+    for flow in flows {
+        if flow.packets.len() < min_packets {
+            continue;
+        }
+        let v = flow.to_infos();
+        let min_seqn = v.iter().map(|x| x.seqn as usize).min().unwrap_or(0);
+        let total = v.iter().map(|x| x.seqn as usize + 1).max().unwrap_or(0);
+        println!(
+            "=== Flow {} -> {}{} ===",
+            flow.src,
+            flow.dst,
+            if flow.rtpmimic { " (RTP mimic)" } else { "" },
+        );
+        if total - min_seqn > v.len().saturating_mul(MAX_SEQN_SPREAD).max(1000) {
+            println!(
+                "Skipped: {} packets span seqn {}..{}, not a netmeasure2 experiment",
+                v.len(),
+                min_seqn,
+                total - 1
+            );
+            println!();
+            continue;
+        }
+        if total > MAX_CAPTURE_TOTAL {
+            println!("Skipped: highest seqn {} is too big", total - 1);
+            println!();
+            continue;
+        }
+        found = true;
+        println!("Highest seqn seen: {}", total - 1);
+        let r = super::analyser::analyse(&v, total, pacing);
+        super::analyser::print_analysis(&r);
+        println!();
+    }
+    if !found {
+        eprintln!("No netmeasure2 data flows found");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::experiment::rawfile::{RawHeader, RawRole};
+    use crate::experiment::statement::{ExperimentDirection, ExperimentInfo};
+
+    fn tmp_path(name: &str) -> ::std::path::PathBuf {
+        ::std::env::temp_dir().join(format!(
+            "netmeasure2-test-{}-{}",
+            ::std::process::id(),
+            name
+        ))
+    }
+
+    fn read_bytes(name: &str, data: &[u8]) -> Result<Vec<Flow>> {
+        let p = tmp_path(name);
+        ::std::fs::write(&p, data)?;
+        let r = read_flows(&p);
+        let _ = ::std::fs::remove_file(&p);
+        r
+    }
+
+    fn synthetic(header: Option<RawHeader>) -> Vec<u8> {
+        let d = RawData {
+            header,
+            total_packets: 4,
+            v: [(0, 0, 5_000), (1, 1_000, 6_100), (3, 3_000, 8_000)]
+                .iter()
+                .map(|&(seqn, st_us, rt_us)| Info { seqn, st_us, rt_us })
+                .collect(),
+        };
+        let mut out = vec![];
+        write_synthetic(&mut out, &d).unwrap();
+        out
+    }
+
+    fn experiment() -> ExperimentInfo {
+        ExperimentInfo {
+            direction: ExperimentDirection::FromServerOnly,
+            packetdelay_us: 1000,
+            packetsize: 200,
+            pending_start_in_microseconds: 2_000_000,
+            rtpmimic: true,
+            pacing: Pacing::Constant,
+            profile: None,
+            payload: Default::default(),
+            session_id: 0x1234_5678_9abc,
+            totalpackets: 4,
+        }
+    }
+
+    #[test]
+    fn synthetic_roundtrip() {
+        let flows = read_bytes("roundtrip", &synthetic(None)).unwrap();
+        assert_eq!(flows.len(), 1);
+        let f = &flows[0];
+        assert_eq!(f.src, SocketAddr::from(CLIENT));
+        assert_eq!(f.dst, SocketAddr::from(SERVER));
+        assert!(!f.rtpmimic);
+        let seqns: Vec<(u32, u32)> = f.packets.iter().map(|x| (x.0, x.1)).collect();
+        assert_eq!(seqns, vec![(0, 0), (1, 1_000), (3, 3_000)]);
+        // Smallest delay becomes zero
+        let infos = f.to_infos();
+        let delays: Vec<u32> = infos.iter().map(|x| x.rt_us - x.st_us).collect();
+        assert_eq!(delays, vec![0, 100, 0]);
+    }
+
+    #[test]
+    fn synthetic_roundtrip_with_header() {
+        let h = RawHeader::new(&experiment(), RawRole::Client, RawDirection::FromServer);
+        let flows = read_bytes("header", &synthetic(Some(h))).unwrap();
+        assert_eq!(flows.len(), 1);
+        let f = &flows[0];
+        assert_eq!(f.src, SocketAddr::from(SERVER));
+        assert!(f.rtpmimic);
+        assert_eq!(f.packets.len(), 3);
+    }
+
+    #[test]
+    fn corrupted_payload_is_skipped() {
+        let mut data = synthetic(None);
+        // Flip a byte of the first packet's payload, after pcap and record headers
+        data[24 + 16 + IP_UDP_HEADERS + MINPACKETSIZE] ^= 1;
+        let flows = read_bytes("corrupted", &data).unwrap();
+        assert_eq!(flows[0].packets.len(), 2);
+    }
+
+    #[test]
+    fn truncated_capture() {
+        let data = synthetic(None);
+        // Cut inside a packet
+        assert!(read_bytes("cut-packet", &data[..data.len() - 10]).is_err());
+        // Cut inside a record header: earlier packets are still read
+        let one = 16 + IP_UDP_HEADERS + MIN_CHECKSUMMED_PACKETSIZE;
+        let flows = read_bytes("cut-header", &data[..24 + 2 * one + 8]).unwrap();
+        assert_eq!(flows[0].packets.len(), 2);
+        // Cut inside the file header
+        assert!(read_bytes("cut-file-header", &data[..10]).is_err());
+    }
+
+    #[test]
+    fn malformed_capture() {
+        let mut data = synthetic(None);
+        data[0] = 0;
+        assert!(read_bytes("bad-magic", &data).is_err());
+
+        // Huge captured length must not be allocated
+        let mut data = synthetic(None);
+        LE::write_u32(&mut data[24 + 8..24 + 12], 0xFFFF_FFF0);
+        assert!(read_bytes("huge-caplen", &data).is_err());
+    }
+
+    #[test]
+    fn frames_of_link_types() {
+        let mut ip = vec![0u8; IP_UDP_HEADERS + 4];
+        ip[0] = 0x45;
+        ip[9] = 17;
+        ip[12..16].copy_from_slice(&[10, 0, 0, 1]);
+        ip[16..20].copy_from_slice(&[10, 0, 0, 2]);
+        BE::write_u16(&mut ip[20..22], 1000);
+        BE::write_u16(&mut ip[22..24], 2000);
+        BE::write_u16(&mut ip[24..26], 8 + 4);
+        ip[28..32].copy_from_slice(b"data");
+
+        let (src, dst, payload) = udp_of_frame(LINKTYPE_RAW, &ip).unwrap();
+        assert_eq!(src, "10.0.0.1:1000".parse().unwrap());
+        assert_eq!(dst, "10.0.0.2:2000".parse().unwrap());
+        assert_eq!(payload, b"data");
+
+        // Ethernet with a VLAN tag, padding and FCS
+        let mut eth = vec![0u8; 12];
+        eth.extend_from_slice(&[0x81, 0x00, 0x00, 0x05, 0x08, 0x00]);
+        eth.extend_from_slice(&ip);
+        eth.extend_from_slice(&[0; 14]);
+        assert_eq!(udp_of_frame(LINKTYPE_ETHERNET, &eth).unwrap().2, b"data");
+
+        // Header length below minimum
+        let mut short_ihl = ip.clone();
+        short_ihl[0] = 0x44;
+        assert!(udp_of_frame(LINKTYPE_RAW, &short_ihl).is_none());
+
+        // Not a first fragment
+        let mut frag = ip.clone();
+        BE::write_u16(&mut frag[6..8], 0x0010);
+        assert!(udp_of_frame(LINKTYPE_RAW, &frag).is_none());
+
+        // Truncated frames
+        assert!(udp_of_frame(LINKTYPE_ETHERNET, &eth[..13]).is_none());
+    }
+
+    #[test]
+    fn short_ipv4_frame() {
+        // As captured with a small snaplen: cut before, within or after the addresses
+        let mut ip = [0u8; IP_UDP_HEADERS];
+        ip[0] = 0x45;
+        ip[9] = 17;
+        for len in 0..IP_UDP_HEADERS {
+            assert!(udp_of_frame(LINKTYPE_RAW, &ip[..len]).is_none());
+        }
+    }
+}
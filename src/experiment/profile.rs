@@ -0,0 +1,117 @@
+use super::pacing::Pacing;
+use super::statement::{ExperimentInfo, MINPACKETSIZE};
+
+/// Named traffic pattern emulating some real application
+#[derive(Debug, EnumString, Display, Serialize, Deserialize, Eq, PartialEq, Copy, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum TrafficProfile {
+    /// Opus voice, 32 kbit/s, 20 ms frames
+    #[strum(serialize = "opus20")]
+    Opus20,
+
+    /// G.711 voice, 64 kbit/s, 20 ms frames
+    #[strum(serialize = "g711")]
+    G711,
+
+    /// 30 fps video, each frame is a train of 4 packets, big I-frame every second
+    #[strum(serialize = "video30")]
+    Video30,
+
+    /// Game client updates, 64 ticks per second, variable size
+    #[strum(serialize = "game")]
+    Game,
+}
+
+pub const ALL_PROFILES: [TrafficProfile; 4] = [
+    TrafficProfile::Opus20,
+    TrafficProfile::G711,
+    TrafficProfile::Video30,
+    TrafficProfile::Game,
+];
+
+const VIDEO_PACKETS_PER_FRAME: u32 = 4;
+
+/// Deterministic pseudo-random number from 0 to 65535
+fn wobble(x: u32) -> u32 {
+    x.wrapping_mul(2_654_435_761) >> 16
+}
+
+impl TrafficProfile {
+    pub fn description(&self) -> &'static str {
+        match self {
+            TrafficProfile::Opus20 => "Voice call (Opus)",
+            TrafficProfile::G711 => "Voice call (G.711)",
+            TrafficProfile::Video30 => "Video call (30 fps)",
+            TrafficProfile::Game => "Online game",
+        }
+    }
+
+    /// Override packet size, timing and RTP mimicking of the experiment
+    pub fn apply(&self, e: &mut ExperimentInfo) {
+        e.profile = Some(*self);
+        e.packetsize = self.max_packetsize();
+        match self {
+            TrafficProfile::Opus20 | TrafficProfile::G711 => {
+                e.packetdelay_us = 20_000;
+                e.pacing = Pacing::Constant;
+                e.rtpmimic = true;
+            }
+            TrafficProfile::Video30 => {
+                e.packetdelay_us = 33_333 / VIDEO_PACKETS_PER_FRAME as u64;
+                e.pacing = Pacing::Train {
+                    length: VIDEO_PACKETS_PER_FRAME,
+                };
+                e.rtpmimic = true;
+            }
+            TrafficProfile::Game => {
+                e.packetdelay_us = 15_625;
+                e.pacing = Pacing::Constant;
+                e.rtpmimic = false;
+            }
+        }
+    }
+
+    pub fn max_packetsize(&self) -> u32 {
+        match self {
+            TrafficProfile::Opus20 => 92,
+            TrafficProfile::G711 => 172,
+            TrafficProfile::Video30 => 1200,
+            TrafficProfile::Game => 200,
+        }
+    }
+
+    /// Size of the packet with the specified sequence number
+    pub fn packetsize(&self, seqn: u32) -> u32 {
+        let sz = match self {
+            TrafficProfile::Opus20 | TrafficProfile::G711 => self.max_packetsize(),
+            TrafficProfile::Video30 => {
+                let frame = seqn / VIDEO_PACKETS_PER_FRAME;
+                if frame.is_multiple_of(30) {
+                    1200
+                } else {
+                    300 + wobble(frame) * 400 / 65536
+                }
+            }
+            TrafficProfile::Game => 60 + wobble(seqn) * 140 / 65536,
+        };
+        sz.max(MINPACKETSIZE as u32)
+    }
+
+    pub fn mean_packetsize(&self) -> u32 {
+        match self {
+            TrafficProfile::Opus20 | TrafficProfile::G711 => self.max_packetsize(),
+            TrafficProfile::Video30 => (1200 + 29 * 500) / 30,
+            TrafficProfile::Game => 130,
+        }
+    }
+
+    /// Human-readable judgement based on quality score (0 to 10)
+    pub fn verdict(&self, score: f32) -> &'static str {
+        match score {
+            x if x >= 8.0 => "would work well",
+            x if x >= 6.0 => "would work with minor glitches",
+            x if x >= 4.0 => "would be noticeably degraded",
+            _ => "would be unusable",
+        }
+    }
+}
@@ -0,0 +1,180 @@
+//! Files with raw per-packet receive data, as saved by `-R`.
+//!
+//! Current format: `MAGIC`, bincode `u32` format version, bincode string with JSON `RawHeader`,
+//! bincode `Vec<Info>`.
+//! Old format (without any header): bincode `usize` total packets, bincode `Vec<Info>`.
+
+use super::receiver::Info;
+use super::statement::ExperimentInfo;
+use crate::Result;
+use ::std::io::{Read, Write};
+use ::std::path::Path;
+
+pub const MAGIC: &[u8; 8] = b"nm2raw\r\n";
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Which peer recorded the data
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Copy, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum RawRole {
+    Client,
+    Server,
+}
+
+/// Which way the recorded packets went
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Copy, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum RawDirection {
+    ToServer,
+    FromServer,
+}
+
+/// Where `rt_us` came from
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Copy, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampSource {
+    /// Monotonic clock read in userspace after `recv`, relative to the experiment start
+    Userspace,
+
+    /// Not measured: sampled from results by `synth`
+    Synthetic,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RawHeader {
+    pub experiment: ExperimentInfo,
+    pub direction: RawDirection,
+    pub role: RawRole,
+    pub timestamp_source: TimestampSource,
+    pub tool_version: String,
+    pub api_version: u32,
+    /// Seconds since UNIX epoch when the file was written
+    pub saved_at: u64,
+    /// Number of packets that were sent, including lost ones
+    pub total_packets: u64,
+}
+
+impl RawHeader {
+    pub fn new(experiment: &ExperimentInfo, role: RawRole, direction: RawDirection) -> Self {
+        RawHeader {
+            experiment: experiment.clone(),
+            direction,
+            role,
+            timestamp_source: TimestampSource::Userspace,
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            api_version: crate::API_VERSION,
+            saved_at: ::std::time::SystemTime::now()
+                .duration_since(::std::time::UNIX_EPOCH)
+                .map(|x| x.as_secs())
+                .unwrap_or(0),
+            total_packets: experiment.totalpackets as u64,
+        }
+    }
+}
+
+/// Contents of a raw data file
+pub struct RawData {
+    /// None for old format files
+    pub header: Option<RawHeader>,
+    pub total_packets: usize,
+    pub v: Vec<Info>,
+}
+
+pub fn save(p: &Path, header: &RawHeader, v: &[Info]) -> Result<()> {
+    let f = ::std::fs::File::create(p)?;
+    let mut f = ::std::io::BufWriter::new(f);
+
+    f.write_all(MAGIC)?;
+    ::bincode::serialize_into(&mut f, &FORMAT_VERSION)?;
+    ::bincode::serialize_into(&mut f, &::serde_json::to_string(header)?)?;
+    ::bincode::serialize_into(&mut f, v)?;
+    f.flush()?;
+    Ok(())
+}
+
+pub fn load(p: &Path) -> Result<RawData> {
+    let mut f = ::std::io::BufReader::new(::std::fs::File::open(p)?);
+    let mut start = [0u8; 8];
+    f.read_exact(&mut start)?;
+
+    if &start != MAGIC {
+        // Old format: those bytes were total number of packets
+        let total_packets: usize = ::bincode::deserialize(&start)?;
+        let v: Vec<Info> = ::bincode::deserialize_from(f)?;
+        return Ok(RawData {
+            header: None,
+            total_packets,
+            v,
+        });
+    }
+
+    let version: u32 = ::bincode::deserialize_from(&mut f)?;
+    ensure!(
+        version == FORMAT_VERSION,
+        "Unsupported raw data format version {}",
+        version
+    );
+    let header: String = ::bincode::deserialize_from(&mut f)?;
+    let header: RawHeader = ::serde_json::from_str(&header)?;
+    let v: Vec<Info> = ::bincode::deserialize_from(f)?;
+    Ok(RawData {
+        total_packets: header.total_packets as usize,
+        header: Some(header),
+        v,
+    })
+}
+
+#[derive(Debug, EnumString, Display, Eq, PartialEq, Copy, Clone)]
+pub enum ExportFormat {
+    /// Space-separated seqn, sending and receiving time in milliseconds;
+    /// header as `#` comments
+    #[strum(serialize = "text")]
+    Text,
+
+    /// Comma-separated values with a header line
+    #[strum(serialize = "csv")]
+    Csv,
+
+    /// Synthetic libpcap capture of received packets
+    #[strum(serialize = "pcap")]
+    Pcap,
+}
+
+/// Output saved raw data in the specified format
+pub fn dump(p: &Path, format: ExportFormat, out: &mut dyn Write) -> Result<()> {
+    let d = load(p)?;
+    match format {
+        ExportFormat::Text => {
+            if let Some(ref h) = d.header {
+                for line in ::serde_json::to_string_pretty(h)?.lines() {
+                    writeln!(out, "# {}", line)?;
+                }
+            }
+            for inf in &d.v {
+                writeln!(
+                    out,
+                    "{} {} {}",
+                    inf.seqn,
+                    inf.st_us as f64 / 1000.0,
+                    inf.rt_us as f64 / 1000.0
+                )?;
+            }
+        }
+        ExportFormat::Csv => {
+            writeln!(out, "seqn,sent_ms,received_ms,delay_ms")?;
+            for inf in &d.v {
+                writeln!(
+                    out,
+                    "{},{},{},{}",
+                    inf.seqn,
+                    inf.st_us as f64 / 1000.0,
+                    inf.rt_us as f64 / 1000.0,
+                    (inf.rt_us as f64 - inf.st_us as f64) / 1000.0
+                )?;
+            }
+        }
+        ExportFormat::Pcap => super::pcap::write_synthetic(out, &d)?,
+    }
+    out.flush()?;
+    Ok(())
+}
@@ -1,7 +1,10 @@
 use ::std::time::{Duration, Instant};
 
+use super::pacing::Pacing;
+use super::payload::PayloadFill;
+use super::rawfile::{RawDirection, RawHeader, RawRole};
 use super::results::{DelayModel, ExperimentResults, LossModel};
-use super::statement::MINPACKETSIZE;
+use super::statement::{ExperimentInfo, MINPACKETSIZE};
 
 use ::byteorder::{ByteOrder, BE};
 
@@ -15,27 +18,57 @@ pub struct Info {
 }
 
 pub struct PacketReceiver {
+    /// First arrivals, then duplicates while there is room for them
     v: Vec<Info>,
+    /// Whether a packet with this seqn was received
+    seen: Vec<bool>,
+    /// Number of first arrivals in `v`
+    unique: usize,
+    /// Duplicates that did not fit into `v`
+    dropped_duplicates: u32,
     start: Instant,
     session_id: u64,
     ctr: usize,
     cur_del_us: f64,
+    pacing: Pacing,
+    payload: PayloadFill,
+    corrupted: u32,
 }
 
+/// Besides a slot for each packet, there is a slot for duplicates per that many packets
+const DUPLICATES_ROOM_DIVISOR: usize = 8;
+
 pub struct PacketReceiverParams {
     pub num_packets: u32,
     pub session_id: u64,
     pub experiment_start: Instant,
+    pub pacing: Pacing,
+    pub payload: PayloadFill,
 }
 
 impl PacketReceiver {
     pub fn recv(&mut self, pkt: &[u8]) {
         assert!(pkt.len() >= MINPACKETSIZE);
-        if self.ctr >= self.v.len() {
+        if !super::payload::verify(pkt, self.session_id, &self.payload) {
+            self.corrupted += 1;
             return;
         }
         let seqn = BE::read_u32(&pkt[12..16]);
         let st_us = BE::read_u32(&pkt[16..20]);
+        let seen = match self.seen.get_mut(seqn as usize) {
+            Some(x) => x,
+            None => return,
+        };
+        if *seen {
+            // Room for all first arrivals is reserved
+            if self.ctr + (self.seen.len() - self.unique) >= self.v.len() {
+                self.dropped_duplicates += 1;
+                return;
+            }
+        } else {
+            *seen = true;
+            self.unique += 1;
+        }
 
         let recv_ts = Instant::now();
         if self.start > recv_ts {
@@ -69,49 +102,109 @@ impl PacketReceiver {
     }
 
     pub fn new(prp: PacketReceiverParams) -> Self {
+        let n = prp.num_packets as usize;
         PacketReceiver {
             start: prp.experiment_start,
             // not just with_capacity to avoid page faults while filling it in
-            v: vec![Default::default(); prp.num_packets as usize],
+            v: vec![Default::default(); n + n / DUPLICATES_ROOM_DIVISOR + 16],
+            seen: vec![false; n],
+            unique: 0,
+            dropped_duplicates: 0,
             session_id: prp.session_id,
             ctr: 0,
             cur_del_us: 0.0,
+            pacing: prp.pacing,
+            payload: prp.payload,
+            corrupted: 0,
         }
     }
 
     pub fn analyse(&self) -> ExperimentResults {
-        let mut r = super::analyser::analyse(&self.v[0..self.ctr], self.v.len());
+        let mut r = super::analyser::analyse(&self.v[0..self.ctr], self.seen.len(), self.pacing);
         r.session_id = self.session_id;
+        r.loss_model.corrupted = self.corrupted;
+        if self.dropped_duplicates > 0 {
+            let lm = &mut r.loss_model;
+            lm.duplicates += self.dropped_duplicates;
+            lm.duplicate_prob =
+                lm.duplicates as f32 / (self.ctr as u32 + self.dropped_duplicates) as f32;
+        }
         r
     }
 
-    #[allow(unused_parens)]
-    pub fn save_raw_data(&self, dir: &::std::path::Path) {
-        if let Err(e) = (try {
-            let p = dir.join(format!("{}.dat", self.session_id));
-            let f = ::std::fs::File::create(p)?;
-            let mut f = ::std::io::BufWriter::new(f);
-
-            ::bincode::serialize_into(&mut f, &self.v.len())?;
-            ::bincode::serialize_into(f, &self.v[0..self.ctr])?;
-        }) {
-            let e: ::anyhow::Error = e;
+    /// Records of received packets, in arrival order
+    pub fn received(&self) -> &[Info] {
+        &self.v[0..self.ctr]
+    }
+
+    pub fn total_packets(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn save_raw_data(
+        &self,
+        dir: &::std::path::Path,
+        experiment: &ExperimentInfo,
+        role: RawRole,
+    ) {
+        let p = dir.join(format!("{}.dat", self.session_id));
+        let direction = match role {
+            RawRole::Client => RawDirection::FromServer,
+            RawRole::Server => RawDirection::ToServer,
+        };
+        let header = RawHeader::new(experiment, role, direction);
+        if let Err(e) = super::rawfile::save(&p, &header, self.received()) {
             eprintln!("Error saving raw receive data: {}", e);
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(seqn: u32, session_id: u64) -> Vec<u8> {
+        let mut pkt = vec![0u8; 64];
+        BE::write_u32(&mut pkt[12..16], seqn);
+        BE::write_u32(&mut pkt[16..20], seqn * 1000);
+        super::super::payload::fill(&mut pkt, session_id, seqn, &PayloadFill::default());
+        pkt
+    }
 
-    pub fn dump_raw_data(p: &::std::path::Path) -> crate::Result<()> {
-        let mut f = ::std::io::BufReader::new(::std::fs::File::open(p)?);
-        let _totpkt: usize = ::bincode::deserialize_from(&mut f)?;
-        let v: Vec<Info> = ::bincode::deserialize_from(f)?;
-        for inf in v {
-            println!(
-                "{} {} {}",
-                inf.seqn,
-                inf.st_us as f64 / 1000.0,
-                inf.rt_us as f64 / 1000.0
-            );
+    fn receive(num_packets: u32, copies: u32) -> ExperimentResults {
+        let mut rcv = PacketReceiver::new(PacketReceiverParams {
+            num_packets,
+            session_id: 42,
+            experiment_start: Instant::now(),
+            pacing: Pacing::Constant,
+            payload: PayloadFill::default(),
+        });
+        for seqn in 0..num_packets {
+            for _ in 0..copies {
+                rcv.recv(&packet(seqn, 42));
+            }
         }
-        Ok(())
+        rcv.analyse()
+    }
+
+    #[test]
+    fn duplicates_are_not_loss() {
+        let r = receive(100, 2);
+        assert_eq!(r.total_received_packets, 100);
+        assert_eq!(r.loss_model.loss_prob, 0.0);
+        assert_eq!(r.loss_model.end_lp, 0);
+        assert_eq!(r.loss_model.duplicates, 100);
+        assert_eq!(r.loss_model.duplicate_prob, 0.5);
+    }
+
+    #[test]
+    fn duplicates_beyond_room_are_counted() {
+        // Far more duplicates than there is room for
+        let r = receive(100, 5);
+        assert_eq!(r.total_received_packets, 100);
+        assert_eq!(r.loss_model.loss_prob, 0.0);
+        assert_eq!(r.loss_model.end_lp, 0);
+        assert_eq!(r.loss_model.duplicates, 400);
+        assert_eq!(r.loss_model.duplicate_prob, 0.8);
     }
 }
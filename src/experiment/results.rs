@@ -1,3 +1,4 @@
+use super::gilbert::GilbertElliott;
 use crate::Result;
 use ::std::rc::Rc;
 
@@ -40,6 +41,30 @@ pub struct DelayModel {
     pub delta_lossmany: [f32; DELAY_DELTAS.len()],
     /// Distribution of delay jumps after losing more than 1 packet
     pub mean_delay_ms: f32,
+
+    /// Interarrival jitter as in RFC 3550
+    #[serde(default)]
+    pub jitter_ms: f32,
+
+    /// Minimum delay
+    #[serde(default)]
+    pub min_ms: f32,
+
+    /// Median delay
+    #[serde(default)]
+    pub p50_ms: f32,
+
+    /// 90th percentile of delay
+    #[serde(default)]
+    pub p90_ms: f32,
+
+    /// 99th percentile of delay
+    #[serde(default)]
+    pub p99_ms: f32,
+
+    /// Maximum delay
+    #[serde(default)]
+    pub max_ms: f32,
 }
 
 // Hard-coded loss (or non-loss) cluster ranges. Must be sorted.
@@ -57,12 +82,78 @@ pub struct LossModel {
     pub sendside_loss: f32,
     pub begin_lp: u32,
     pub end_lp: u32,
+
+    /// Number of received packets that duplicated already received ones
+    #[serde(default)]
+    pub duplicates: u32,
+
+    /// Share of duplicates among all received packets
+    #[serde(default)]
+    pub duplicate_prob: f32,
+
+    /// Mean time between arrivals of original packet and its duplicate
+    #[serde(default)]
+    pub duplicate_mean_lag_ms: f32,
+
+    /// Number of packets with wrong checksum or payload. They are counted as lost.
+    #[serde(default)]
+    pub corrupted: u32,
+
+    /// Gilbert model (no loss in Good state) fitted to the loss sequence
+    #[serde(default)]
+    pub gilbert: Option<GilbertElliott>,
+
+    /// Gilbert-Elliott model fitted to the loss sequence
+    #[serde(default)]
+    pub gilbert_elliott: Option<GilbertElliott>,
+}
+
+// Hard-coded reordering extent and distance ranges, in packets. Must be sorted.
+counted_array!(
+pub const REORDER_EXTENTS: [i32; _] = [
+    1, 2, 3, 4, 5, 7, 10, 15, 20, 30, 50, 100, 200, 500, 65535,
+]);
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct ReorderModel {
+    /// Distribution of RFC 4737 reordering extents of reordered packets:
+    /// how many packets arrived between the reordered one and
+    /// the earliest arrived packet with bigger sequence number
+    pub extent: [f32; REORDER_EXTENTS.len()],
+
+    /// Distribution of how much sequence number of a reordered packet
+    /// is behind the maximum one received before
+    pub distance: [f32; REORDER_EXTENTS.len()],
+
+    /// Share of received packets that arrived after a packet with bigger sequence number
+    pub reorder_prob: f32,
+    pub max_extent: u32,
+}
+
+/// Upper limit of number of intervals in a time series
+pub const MAX_INTERVALS: usize = 100;
+
+/// Experiment split into equal intervals of sending time
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct TimeSeries {
+    pub interval_ms: u32,
+    pub received: Vec<u32>,
+    pub lost: Vec<u32>,
+
+    /// None if no packets were received within the interval
+    pub min_delay_ms: Vec<Option<i32>>,
+    pub mean_delay_ms: Vec<Option<i32>>,
+    pub max_delay_ms: Vec<Option<i32>>,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct ExperimentResults {
     pub delay_model: DelayModel,
     pub loss_model: LossModel,
+    #[serde(default)]
+    pub reorder_model: ReorderModel,
+    #[serde(default)]
+    pub time_series: TimeSeries,
     pub session_id: u64,
     pub total_received_packets: u32,
 }
@@ -78,9 +169,12 @@ pub struct ResultsForStoring {
     pub api_version: u32,
 }
 
-#[allow(unused)]
-const ER_SIZE: usize = ::std::mem::size_of::<ExperimentResults>() * 3 / 2 + 64;
-const_assert!(ER_SIZE < 1420);
+/// Maximum size of a server reply, so it does not get IP-fragmented.
+/// Results have variable length, so it is checked when sending.
+pub const MAX_RESULTS_DATAGRAM: usize = 1400;
+
+/// Encoded results bigger than that are sent in `ResultsPart` pieces of that size
+pub const RESULTS_PART_BYTES: usize = 1200;
 
 pub fn dump_some_results() -> Result<()> {
     let mut r = ExperimentResults::default();
@@ -107,6 +201,12 @@ pub fn dump_some_results() -> Result<()> {
     for v in r.loss_model.loss.iter_mut() {
         *v = rnd.gen();
     }
+    for v in r.reorder_model.extent.iter_mut() {
+        *v = rnd.gen();
+    }
+    for v in r.reorder_model.distance.iter_mut() {
+        *v = rnd.gen();
+    }
     let rpl = super::statement::ExperimentReply::HereAreResults {
         stats: Some(Rc::new(r)),
         send_lost: None,
@@ -1,3 +1,6 @@
+use super::pacing::Pacing;
+use super::payload::PayloadFill;
+use super::profile::TrafficProfile;
 use super::statement::MINPACKETSIZE;
 use crate::experiment::SmallishDuration;
 use crate::Result;
@@ -15,6 +18,9 @@ pub struct Sender {
     pub packetcount: u32,
     pub experiment_start: Instant,
     pub delay_between_packets: Duration,
+    pub pacing: Pacing,
+    pub profile: Option<TrafficProfile>,
+    pub payload: PayloadFill,
     pub session_id: u64,
 }
 
@@ -44,9 +50,12 @@ impl Sender {
 
         eprintln!("Sender started");
 
-        for seqn in 0..self.packetcount {
+        let schedule = self
+            .pacing
+            .schedule(self.delay_between_packets.as_micros() as u64);
+        for (seqn, offset) in (0..self.packetcount).zip(schedule) {
             let n = now();
-            let t = self.experiment_start + self.delay_between_packets * seqn;
+            let t = self.experiment_start + offset;
             if n <= t {
                 sleeper.sleep(t - n);
             //::std::thread::sleep(t-n);
@@ -71,7 +80,14 @@ impl Sender {
                 BE::write_u32(&mut pkt[4..8], ts * 90 / 1000);
             }
 
-            if let Err(_) = udp.send_to(&pkt[..], to) {
+            let len = match self.profile {
+                Some(p) => (p.packetsize(seqn) as usize).min(self.packetsize),
+                None => self.packetsize,
+            };
+
+            super::payload::fill(&mut pkt[..len], self.session_id, seqn, &self.payload);
+
+            if let Err(_) = udp.send_to(&pkt[..len], to) {
                 lost += 1;
             }
         }
@@ -8,8 +8,17 @@ use ::std::time::Duration;
 
 use ::structopt::clap::Arg;
 
+use super::pacing::Pacing;
+use super::payload::PayloadFill;
+use super::profile::TrafficProfile;
+
 pub const MINPACKETSIZE: usize = 20;
 
+/// Number of raw receive records in one `ExperimentReply::RawData`.
+/// A record is three u32s, at most 15 bytes of CBOR, so a chunk fits one datagram.
+pub const RAW_CHUNK_RECORDS: usize = 80;
+const_assert!(RAW_CHUNK_RECORDS * 15 + 200 <= super::results::MAX_RESULTS_DATAGRAM);
+
 #[derive(Debug, EnumString, Display, Serialize, Deserialize, Eq, PartialEq, Copy, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum ExperimentDirection {
@@ -63,20 +72,42 @@ pub struct ExperimentInfo {
     #[structopt(long = "direction", default_value = "both")]
     pub direction: ExperimentDirection,
 
+    /// Pacing: constant | poisson | onoff:<burst>:<gap_us> | train:<length>
+    #[structopt(long = "pacing", default_value = "constant")]
+    #[serde(default)]
+    pub pacing: Pacing,
+
     /// Make packets looks like RTP
     #[structopt(long = "rtpmimic")]
     pub rtpmimic: bool,
 
+    /// Payload: random (incompressible) | zeros | pattern:<hex bytes>
+    #[structopt(long = "payload", default_value = "random")]
+    #[serde(default)]
+    pub payload: PayloadFill,
+
+    /// Emulate application: opus20 | g711 | video30 | game.
+    /// Overrides packet size, delay, pacing and RTP mimicking.
+    #[structopt(long = "profile")]
+    #[serde(default)]
+    pub profile: Option<TrafficProfile>,
+
     /// Internal parameter, no need to be set
     #[structopt(long = "sessionid", default_value = "0")]
+    #[serde(default)]
     pub session_id: u64,
 
     /// In microseconds
     #[structopt(long = "warmup_time", default_value = "2000000")]
     #[derivative(PartialEq = "ignore")]
+    #[serde(default = "default_warmup_time")]
     pub pending_start_in_microseconds: u32,
 }
 
+fn default_warmup_time() -> u32 {
+    2_000_000
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 #[serde(tag = "type")]
@@ -103,6 +134,55 @@ pub enum ExperimentReply {
         stats: Option<Rc<super::results::ExperimentResults>>,
         send_lost: Option<u32>,
     },
+    /// Part of results too big for one datagram: CBOR of `HereAreResults`' `stats`,
+    /// split into `parts` pieces. Retry with `results_part` to get other pieces.
+    ResultsPart {
+        part: u32,
+        parts: u32,
+        send_lost: Option<u32>,
+        #[serde(with = "cbor_bytes")]
+        data: Vec<u8>,
+    },
+    /// Part of raw receive data of the completed experiment, starting from `offset`.
+    /// Records are flattened (seqn, st_us, rt_us) triples.
+    RawData {
+        offset: u32,
+        received: u32,
+        total_packets: u32,
+        records: Vec<u32>,
+    },
     /// There was some failure on server
     Failed { msg: String },
 }
+
+/// Byte string instead of array of numbers, which is twice as big in CBOR
+mod cbor_bytes {
+    use ::serde::de::{Deserializer, Error, Visitor};
+    use ::serde::Serializer;
+
+    pub fn serialize<S: Serializer>(v: &[u8], s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_bytes(v)
+    }
+
+    struct BytesVisitor;
+
+    impl<'de> Visitor<'de> for BytesVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+            f.write_str("byte string")
+        }
+
+        fn visit_bytes<E: Error>(self, v: &[u8]) -> Result<Vec<u8>, E> {
+            Ok(v.to_vec())
+        }
+
+        fn visit_byte_buf<E: Error>(self, v: Vec<u8>) -> Result<Vec<u8>, E> {
+            Ok(v)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u8>, D::Error> {
+        d.deserialize_byte_buf(BytesVisitor)
+    }
+}
@@ -1,6 +1,6 @@
 use super::receiver::Info;
 use super::results::{DelayModel, ExperimentResults, LossModel, ResultsForStoring};
-use super::results::{CLUSTERS, DELAY_DELTAS, DELAY_VALUES};
+use super::results::{CLUSTERS, DELAY_DELTAS, DELAY_VALUES, REORDER_EXTENTS};
 use crate::Result;
 
 fn bar(mut x: f32) -> &'static str {
@@ -172,6 +172,12 @@ impl ExperimentResults {
     }
 
     pub fn visualise_delay(&self) {
+        let dm = &self.delay_model;
+        println!(
+            "Delay p50/p90/p99/max: {:.0}/{:.0}/{:.0}/{:.0}ms, jitter (RFC 3550): {:.1}ms",
+            dm.p50_ms, dm.p90_ms, dm.p99_ms, dm.max_ms, dm.jitter_ms,
+        );
+
         let mut delay_report = vec![];
 
         delay_report.push(format!(
@@ -282,29 +288,169 @@ impl ExperimentResults {
     }
 }
 
+impl ExperimentResults {
+    pub fn visualise_anomalies(&self) {
+        let lm = &self.loss_model;
+        if lm.duplicates > 0 {
+            println!(
+                "Duplicated packets: {} ({:3.2}%), arriving {:.1}ms after originals on average",
+                lm.duplicates,
+                lm.duplicate_prob * 100.0,
+                lm.duplicate_mean_lag_ms,
+            );
+        }
+        if lm.corrupted > 0 {
+            println!(
+                "Packets with corrupted or modified payload: {}",
+                lm.corrupted
+            );
+        }
+    }
+
+    pub fn visualise_loss_models(&self) {
+        let lm = &self.loss_model;
+        let models = [
+            ("Gilbert", lm.gilbert),
+            ("Gilbert-Elliott", lm.gilbert_elliott),
+        ];
+        if models.iter().all(|(_, m)| m.is_none()) {
+            // Results from older versions
+            if let Some(m) = lm.gilbert_from_clusters() {
+                println!("Simple Gilbert model (from histogram): {}", m.netem());
+            }
+            return;
+        }
+        for (name, m) in models.iter() {
+            if let Some(m) = m {
+                println!(
+                    "{} model: p={:.4} r={:.4} h={:.4} k={:.4} => {}",
+                    name,
+                    m.p,
+                    m.r,
+                    m.h,
+                    m.k,
+                    m.netem()
+                );
+            }
+        }
+    }
+
+    pub fn visualise_reorder(&self) {
+        let rm = &self.reorder_model;
+        if rm.reorder_prob == 0.0 {
+            println!("No reordering");
+            return;
+        }
+        println!(
+            "Reordered packets: {:3.2}%, maximum extent: {}",
+            rm.reorder_prob * 100.0,
+            rm.max_extent,
+        );
+
+        fn report(title: &str, v: &[f32]) -> Vec<String> {
+            let mut report = vec![title.to_string()];
+            let mut prevskipped = false;
+            for (i, &c) in REORDER_EXTENTS.iter().enumerate() {
+                let x = v[i];
+
+                if x < 0.001 {
+                    if !prevskipped {
+                        report.push("...".to_string());
+                        prevskipped = true;
+                    }
+                    continue;
+                } else {
+                    prevskipped = false;
+                }
+
+                let header = if c != 65535 {
+                    format!("{:3}", c)
+                } else {
+                    "UUU".to_string()
+                };
+
+                report.push(format!("{:3} {:1.4} {:8}", header, x, bar(x),));
+            }
+            report
+        }
+        print_side_by_side(
+            &report("Reorder extent:", &rm.extent),
+            &report("Reorder distance:", &rm.distance),
+        );
+    }
+}
+
+impl ExperimentResults {
+    pub fn visualise_timeline(&self) {
+        let ts = &self.time_series;
+        if ts.received.is_empty() {
+            return;
+        }
+        println!("Timeline (per {}ms):", ts.interval_ms);
+        println!("  time,s | rcvd  lost          | delay min  mean   max");
+        fn d(x: Option<i32>) -> String {
+            match x {
+                Some(x) => format!("{:5}", x),
+                None => "    -".to_string(),
+            }
+        }
+        for i in 0..ts.received.len() {
+            let lossfrac = ts.lost[i] as f32 / (ts.lost[i] + ts.received[i]).max(1) as f32;
+            println!(
+                "{:8.1} | {:5} {:5} {:8}|     {} {} {}",
+                (i as u32 * ts.interval_ms) as f32 / 1000.0,
+                ts.received[i],
+                ts.lost[i],
+                bar(lossfrac),
+                d(ts.min_delay_ms[i]),
+                d(ts.mean_delay_ms[i]),
+                d(ts.max_delay_ms[i]),
+            );
+        }
+    }
+}
+
 impl ResultsForStoring {
     pub fn print_to_stdout(&self) {
         println!("Experiment params: {:?}", self.conditions);
         println!("RTT={}ms", self.rtt_us / 1000);
-        fn q(r: &ExperimentResults) {
+        fn q(r: &ExperimentResults, voice_base_delay_ms: Option<f32>) {
             println!(
                 "Total received packets: {} (loss {:3.2}%, send-side loss: {:3.2}%)",
                 r.total_received_packets,
                 r.loss_model.loss_prob * 100.0,
                 r.loss_model.sendside_loss * 100.0,
             );
+            if let Some(base_delay_ms) = voice_base_delay_ms {
+                let vq = r.voice_quality(base_delay_ms);
+                println!(
+                    "Voice quality (E-model): R={:.0} MOS={:.2}",
+                    vq.r_factor, vq.mos
+                );
+            }
+            r.visualise_anomalies();
             r.visualise_loss();
+            r.visualise_loss_models();
             println!();
             r.visualise_delay();
             println!();
+            r.visualise_reorder();
+            println!();
+            r.visualise_timeline();
+            println!();
         }
+        let voice_base_delay_ms = if self.conditions.rtpmimic {
+            Some(self.rtt_us as f32 / 2000.0)
+        } else {
+            None
+        };
         if let Some(ref to_server) = self.to_server {
             println!("** To server: ***");
-            q(to_server);
+            q(to_server, voice_base_delay_ms);
         };
         if let Some(ref from_server) = self.from_server {
             println!("** From server: ***");
-            q(from_server);
+            q(from_server, voice_base_delay_ms);
         };
         use crate::experiment::SmallishDuration;
         println!(
@@ -312,6 +458,10 @@ impl ResultsForStoring {
             self.conditions.bytes_used() as f32 * 0.001 * 0.001,
             self.conditions.kbps() as f32 / 1000.0,
         );
+        if let Some(profile) = self.conditions.profile {
+            let (_, score) = self.short_summary();
+            println!("{} {}", profile.description(), profile.verdict(score));
+        }
     }
 }
 
@@ -0,0 +1,328 @@
+//! Local store of past runs, for following links over months.
+//!
+//! Store is a directory with append-only `index.jsonl` (one `IndexEntry` per line)
+//! and `runs/` subdirectory with full results of each run as battery-style JSON array,
+//! so `showbat` and `batdiff` work on them.
+
+use crate::battery::visualise::{overall_score, ScoreEntry};
+use crate::experiment::results::ResultsForStoring;
+use crate::Result;
+use ::std::io::{BufRead, Write};
+use ::std::path::{Path, PathBuf};
+use ::structopt::StructOpt;
+
+const SECONDS_PER_DAY: u64 = 86400;
+
+#[derive(Debug, StructOpt, Clone)]
+pub struct RecordOpts {
+    /// Also save results to the history store, for `history` command
+    #[structopt(long = "record")]
+    pub record: bool,
+
+    /// Label of the recorded run, e.g. router firmware version. May be repeated.
+    #[structopt(long = "label", number_of_values = 1)]
+    pub labels: Vec<String>,
+
+    /// History store directory. Default is $NETMEASURE2_HISTORY
+    /// or ~/.local/share/netmeasure2/history
+    #[structopt(long = "history-dir", parse(from_os_str))]
+    pub history_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Copy, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum RunKind {
+    Probe,
+    Battery,
+}
+
+/// One line of `index.jsonl`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct IndexEntry {
+    /// Seconds since UNIX epoch
+    pub recorded_at: u64,
+    pub server: String,
+    pub kind: RunKind,
+    #[serde(default)]
+    pub labels: Vec<String>,
+    /// Results file, relative to the store directory
+    pub file: String,
+    pub experiments: u32,
+    pub score: f32,
+    /// From 0 to 1
+    pub availability: f32,
+    /// Mean loss of all directions of all experiments, from 0 to 1
+    pub loss: f32,
+    /// Mean delay of all directions of all experiments
+    pub delay_ms: f32,
+}
+
+fn history_dir(d: &Option<PathBuf>) -> Result<PathBuf> {
+    if let Some(d) = d {
+        return Ok(d.clone());
+    }
+    if let Some(d) = ::std::env::var_os("NETMEASURE2_HISTORY") {
+        return Ok(d.into());
+    }
+    let data = match ::std::env::var_os("XDG_DATA_HOME") {
+        Some(d) => PathBuf::from(d),
+        None => match ::std::env::var_os("HOME") {
+            Some(h) => PathBuf::from(h).join(".local/share"),
+            None => bail!("Cannot find history store directory, use --history-dir"),
+        },
+    };
+    Ok(data.join("netmeasure2/history"))
+}
+
+fn now() -> u64 {
+    ::std::time::SystemTime::now()
+        .duration_since(::std::time::UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0)
+}
+
+/// `YYYY-MM-DD` of UTC day number since UNIX epoch
+fn format_day(days: u64) -> String {
+    // Civil from days, proleptic Gregorian calendar
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", y, m, d)
+}
+
+fn summarize(v: &[ResultsForStoring]) -> (f32, f32, f32, f32) {
+    let mut scores = Vec::with_capacity(v.len());
+    let mut loss = 0.0;
+    let mut delay_ms = 0.0;
+    let mut n = 0;
+    for entry in v {
+        scores.push(ScoreEntry::new(&entry.conditions, entry.short_summary().1));
+        for r in entry.to_server.iter().chain(entry.from_server.iter()) {
+            loss += r.loss_model.loss_prob;
+            delay_ms += r.delay_model.mean_delay_ms;
+            n += 1;
+        }
+    }
+    let (score, availability) = overall_score(&scores);
+    let n = n.max(1) as f32;
+    (score, availability, loss / n, delay_ms / n)
+}
+
+/// Save results of a run to the history store, if `--record` is specified
+pub fn record(
+    opts: &RecordOpts,
+    server: &::std::net::SocketAddr,
+    kind: RunKind,
+    v: &[ResultsForStoring],
+) -> Result<()> {
+    if !opts.record {
+        return Ok(());
+    }
+    let dir = history_dir(&opts.history_dir)?;
+    ::std::fs::create_dir_all(dir.join("runs"))?;
+
+    let recorded_at = now();
+    let kind_name = match kind {
+        RunKind::Probe => "probe",
+        RunKind::Battery => "battery",
+    };
+    // Several runs may be recorded within a second
+    let mut i = 0;
+    let (file, f) = loop {
+        let file = format!("runs/{}-{}-{}.json", recorded_at, kind_name, i);
+        match ::std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(dir.join(&file))
+        {
+            Ok(f) => break (file, f),
+            Err(e) if e.kind() == ::std::io::ErrorKind::AlreadyExists => i += 1,
+            Err(e) => return Err(e.into()),
+        }
+    };
+    let mut f = ::std::io::BufWriter::new(f);
+    ::serde_json::ser::to_writer(&mut f, &v)?;
+    writeln!(f)?;
+    f.flush()?;
+
+    let (score, availability, loss, delay_ms) = summarize(v);
+    let entry = IndexEntry {
+        recorded_at,
+        server: server.to_string(),
+        kind,
+        labels: opts.labels.clone(),
+        file,
+        experiments: v.len() as u32,
+        score,
+        availability,
+        loss,
+        delay_ms,
+    };
+    let mut index = ::std::fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(dir.join("index.jsonl"))?;
+    // Single write, so concurrent runs don't interleave lines
+    let mut line = ::serde_json::to_string(&entry)?;
+    line.push('\n');
+    index.write_all(line.as_bytes())?;
+    eprintln!("Recorded to {:?}", dir.join(&entry.file));
+    Ok(())
+}
+
+pub fn load_index(dir: &Path) -> Result<Vec<IndexEntry>> {
+    let f = ::std::io::BufReader::new(::std::fs::File::open(dir.join("index.jsonl"))?);
+    let mut v = vec![];
+    for line in f.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        // Skip a line torn by interrupted write instead of failing entire history
+        match ::serde_json::from_str(&line) {
+            Ok(e) => v.push(e),
+            Err(e) => eprintln!("Skipping bad index line: {}", e),
+        }
+    }
+    Ok(v)
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Cmd {
+    /// Show only runs against this server, as specified for `probe` or `battery`
+    server: Option<String>,
+
+    /// Show only runs having this label. May be repeated.
+    #[structopt(long = "label", number_of_values = 1)]
+    labels: Vec<String>,
+
+    /// Show only runs of that many last days
+    #[structopt(long = "days", default_value = "90")]
+    days: u64,
+
+    /// Show only battery runs (probes are less representative)
+    #[structopt(long = "battery-only")]
+    battery_only: bool,
+
+    /// List each run instead of daily averages
+    #[structopt(long = "verbose", short = "v")]
+    verbose: bool,
+
+    /// History store directory. Default is $NETMEASURE2_HISTORY
+    /// or ~/.local/share/netmeasure2/history
+    #[structopt(long = "history-dir", parse(from_os_str))]
+    history_dir: Option<PathBuf>,
+}
+
+/// Score, availability, loss and delay averaged over some runs
+#[derive(Default)]
+struct Trend {
+    runs: u32,
+    score: f32,
+    availability: f32,
+    loss: f32,
+    delay_ms: f32,
+}
+
+impl Trend {
+    fn add(&mut self, e: &IndexEntry) {
+        self.runs += 1;
+        self.score += e.score;
+        self.availability += e.availability;
+        self.loss += e.loss;
+        self.delay_ms += e.delay_ms;
+    }
+
+    fn print(&self, when: &str, extra: &str) {
+        let n = self.runs.max(1) as f32;
+        let score = self.score / n;
+        println!(
+            "{:<19} | {:4} | {:5.1} {:<10} | {:4.0}% | {:6.2}% | {:8.1} {}",
+            when,
+            self.runs,
+            score,
+            "#".repeat((score.clamp(0.0, 10.0)) as usize),
+            self.availability / n * 100.0,
+            self.loss / n * 100.0,
+            self.delay_ms / n,
+            extra,
+        );
+    }
+}
+
+impl Cmd {
+    pub fn run(self) -> Result<()> {
+        let dir = history_dir(&self.history_dir)?;
+        let since = now().saturating_sub(self.days * SECONDS_PER_DAY);
+        let mut v: Vec<IndexEntry> = load_index(&dir)?
+            .into_iter()
+            .filter(|e| e.recorded_at >= since)
+            .filter(|e| self.server.as_ref().is_none_or(|s| *s == e.server))
+            .filter(|e| self.labels.iter().all(|l| e.labels.contains(l)))
+            .filter(|e| !self.battery_only || e.kind == RunKind::Battery)
+            .collect();
+        // Clock may have been adjusted between runs
+        v.sort_by_key(|e| e.recorded_at);
+        if v.is_empty() {
+            println!("No recorded runs match");
+            return Ok(());
+        }
+
+        println!(
+            "{:<19} | runs | score            | avail | loss    | delay_ms",
+            if self.verbose {
+                "time (UTC)"
+            } else {
+                "day (UTC)"
+            }
+        );
+        let mut days: ::std::collections::BTreeMap<u64, Trend> = Default::default();
+        for e in &v {
+            if self.verbose {
+                let mut t = Trend::default();
+                t.add(e);
+                let secs = e.recorded_at % SECONDS_PER_DAY;
+                let when = format!(
+                    "{} {:02}:{:02}:{:02}",
+                    format_day(e.recorded_at / SECONDS_PER_DAY),
+                    secs / 3600,
+                    secs / 60 % 60,
+                    secs % 60
+                );
+                let extra = format!("{} {} {}", e.server, e.labels.join(","), e.file);
+                t.print(&when, &extra);
+            }
+            days.entry(e.recorded_at / SECONDS_PER_DAY)
+                .or_default()
+                .add(e);
+        }
+        if !self.verbose {
+            for (day, t) in &days {
+                t.print(&format_day(*day), "");
+            }
+        }
+
+        let first = days.iter().next();
+        let last = days.iter().next_back();
+        if let (Some((first_day, first)), Some((last_day, last))) = (first, last) {
+            if first_day != last_day {
+                let avg = |x: f32, t: &Trend| x / t.runs.max(1) as f32;
+                println!(
+                    "Change from {} to {}: score {:+.1}, loss {:+.2}%, delay {:+.1}ms",
+                    format_day(*first_day),
+                    format_day(*last_day),
+                    avg(last.score, last) - avg(first.score, first),
+                    (avg(last.loss, last) - avg(first.loss, first)) * 100.0,
+                    avg(last.delay_ms, last) - avg(first.delay_ms, first),
+                );
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,466 @@
+//! UDP relay that degrades traffic passing through it, for testing without a bad network
+
+use crate::experiment::generator::TraceGenerator;
+use crate::experiment::gilbert::GilbertElliott;
+use crate::experiment::statement::ExperimentDirection;
+use crate::Result;
+use ::rand::{Rng, SeedableRng};
+use ::rand_xorshift::XorShiftRng;
+use ::std::collections::{BinaryHeap, HashMap, VecDeque};
+use ::std::io::ErrorKind;
+use ::std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use ::std::sync::atomic::{AtomicBool, Ordering};
+use ::std::sync::{mpsc, Arc};
+use ::std::time::{Duration, Instant};
+use ::structopt::StructOpt;
+
+#[derive(Debug, StructOpt, Clone)]
+pub struct Impairments {
+    /// Constant delay to add, milliseconds
+    #[structopt(long = "delay", default_value = "0")]
+    pub delay_ms: f32,
+
+    /// Add uniformly distributed random delay from -jitter to +jitter, milliseconds.
+    /// Packets may get reordered because of it.
+    #[structopt(long = "jitter", default_value = "0")]
+    pub jitter_ms: f32,
+
+    /// Random (Bernoulli) loss, percent
+    #[structopt(long = "loss", default_value = "0")]
+    pub loss_percent: f32,
+
+    /// Gilbert-Elliott loss as `p:r:1-h:1-k` in percent, like `tc netem loss gemodel`.
+    /// Applied in addition to `--loss`.
+    #[structopt(long = "loss-ge")]
+    pub loss_ge: Option<GilbertElliott>,
+
+    /// Percent of packets to hold back by `--reorder-gap`, so that following packets overtake them
+    #[structopt(long = "reorder", default_value = "0")]
+    pub reorder_percent: f32,
+
+    /// Additional delay for held back packets, milliseconds
+    #[structopt(long = "reorder-gap", default_value = "10")]
+    pub reorder_gap_ms: f32,
+
+    /// Percent of packets to send twice
+    #[structopt(long = "duplicate", default_value = "0")]
+    pub duplicate_percent: f32,
+
+    /// Bottleneck rate, kilobits per second. 0 means unlimited.
+    #[structopt(long = "rate", default_value = "0")]
+    pub rate_kbps: u32,
+
+    /// Bottleneck queue length, packets. Packets that don't fit are dropped.
+    #[structopt(long = "queue", default_value = "1000")]
+    pub queue_packets: usize,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Cmd {
+    /// UDP port to accept clients on
+    listen: SocketAddr,
+
+    /// `netmeasure2 serve` instance to relay to
+    server: SocketAddr,
+
+    #[structopt(flatten)]
+    imp: Impairments,
+
+    /// Which way to impair: send (to server) | recv (from server) | both
+    #[structopt(long = "direction", default_value = "both")]
+    direction: ExperimentDirection,
+
+    /// Also impair control (negotiation and results) packets, not only data packets
+    #[structopt(long = "impair-control")]
+    impair_control: bool,
+
+    /// Seed for random decisions, for reproducible runs
+    #[structopt(long = "seed", default_value = "1")]
+    seed: u64,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+enum Dir {
+    ToServer,
+    FromServer,
+}
+
+struct Incoming {
+    dir: Dir,
+    client: SocketAddr,
+    data: Vec<u8>,
+}
+
+/// Per-client socket towards the server
+struct Upstream {
+    socket: UdpSocket,
+    last_active: Instant,
+    /// Cleared when the client expires, so that its receiver thread exits
+    alive: Arc<AtomicBool>,
+}
+
+/// Clients silent in both directions for that long get their upstream socket closed
+const CLIENT_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Packet waiting in the heap to be sent out
+struct Outgoing {
+    release: Instant,
+    seq: u64,
+    dir: Dir,
+    client: SocketAddr,
+    data: Vec<u8>,
+}
+
+impl PartialEq for Outgoing {
+    fn eq(&self, other: &Self) -> bool {
+        self.release == other.release && self.seq == other.seq
+    }
+}
+impl Eq for Outgoing {}
+impl PartialOrd for Outgoing {
+    fn partial_cmp(&self, other: &Self) -> Option<::std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Outgoing {
+    // Reversed, so that `BinaryHeap` pops the earliest packet
+    fn cmp(&self, other: &Self) -> ::std::cmp::Ordering {
+        (other.release, other.seq).cmp(&(self.release, self.seq))
+    }
+}
+
+#[derive(Default, Debug)]
+struct Counters {
+    received: u64,
+    lost: u64,
+    queue_dropped: u64,
+    duplicated: u64,
+    reordered: u64,
+}
+
+/// Impairment state of one direction
+pub struct Link {
+    imp: Impairments,
+    rng: XorShiftRng,
+    ge_bad: bool,
+    link_free: Instant,
+    /// Times when packets in the bottleneck queue finish being transmitted
+    queue: VecDeque<Instant>,
+    counters: Counters,
+    /// Decides loss and delay instead of `imp`'s loss and jitter settings
+    model: Option<TraceGenerator>,
+}
+
+/// What to do with one packet
+pub enum Verdict {
+    Drop,
+    /// Release times of the packet and its copies
+    Send(Vec<Instant>),
+}
+
+impl Link {
+    pub fn new(imp: Impairments, seed: u64) -> Self {
+        Link {
+            imp,
+            rng: XorShiftRng::seed_from_u64(seed),
+            ge_bad: false,
+            link_free: Instant::now(),
+            queue: VecDeque::new(),
+            counters: Default::default(),
+            model: None,
+        }
+    }
+
+    /// Replay loss and delay of a measured network. Delay is added to `imp`'s one.
+    pub fn with_model(mut self, model: TraceGenerator) -> Self {
+        self.model = Some(model);
+        self
+    }
+
+    fn chance(&mut self, percent: f32) -> bool {
+        percent > 0.0 && self.rng.gen::<f32>() * 100.0 < percent
+    }
+
+    fn loses(&mut self) -> bool {
+        let bernoulli = self.chance(self.imp.loss_percent);
+        let ge = match self.imp.loss_ge {
+            Some(ge) => {
+                let passes = if self.ge_bad { ge.h } else { ge.k };
+                let lost = self.rng.gen::<f32>() >= passes;
+                let switch = if self.ge_bad { ge.r } else { ge.p };
+                if self.rng.gen::<f32>() < switch {
+                    self.ge_bad = !self.ge_bad;
+                }
+                lost
+            }
+            None => false,
+        };
+        bernoulli || ge
+    }
+
+    /// Decide fate of a packet of `len` bytes arriving `now`
+    pub fn process(&mut self, now: Instant, len: usize) -> Verdict {
+        self.counters.received += 1;
+        let mut model_delay_ms = 0.0;
+        let lost = match self.model {
+            Some(ref mut model) => match model.next_packet() {
+                Some(d) => {
+                    model_delay_ms = d as f32;
+                    false
+                }
+                None => true,
+            },
+            None => self.loses(),
+        };
+        if lost {
+            self.counters.lost += 1;
+            return Verdict::Drop;
+        }
+
+        // Bottleneck: wait in the queue, then get transmitted at `rate_kbps`
+        let mut t = now;
+        if self.imp.rate_kbps > 0 {
+            while self.queue.front().is_some_and(|&x| x <= now) {
+                self.queue.pop_front();
+            }
+            if self.queue.len() >= self.imp.queue_packets {
+                self.counters.queue_dropped += 1;
+                return Verdict::Drop;
+            }
+            let start = self.link_free.max(now);
+            let tx_us = (len as u64 + 28) * 8 * 1000 / self.imp.rate_kbps as u64;
+            self.link_free = start + Duration::from_micros(tx_us);
+            self.queue.push_back(self.link_free);
+            t = self.link_free;
+        }
+
+        let mut copies = 1;
+        if self.chance(self.imp.duplicate_percent) {
+            self.counters.duplicated += 1;
+            copies = 2;
+        }
+        let mut releases = Vec::with_capacity(copies);
+        for _ in 0..copies {
+            let mut delay_ms = self.imp.delay_ms + model_delay_ms;
+            if self.imp.jitter_ms > 0.0 && self.model.is_none() {
+                delay_ms += self.rng.gen_range(-self.imp.jitter_ms, self.imp.jitter_ms);
+            }
+            if self.chance(self.imp.reorder_percent) {
+                self.counters.reordered += 1;
+                delay_ms += self.imp.reorder_gap_ms;
+            }
+            releases.push(t + Duration::from_micros((delay_ms.max(0.0) * 1000.0) as u64));
+        }
+        Verdict::Send(releases)
+    }
+}
+
+fn is_control(data: &[u8]) -> bool {
+    data.len() >= 3 && &data[0..3] == b"\xd9\xd9\xf7"
+}
+
+fn unspecified_like(sa: SocketAddr) -> SocketAddr {
+    if sa.is_ipv6() {
+        SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0)
+    } else {
+        SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0)
+    }
+}
+
+impl Cmd {
+    pub fn run(self) -> Result<()> {
+        ensure!(
+            self.imp.queue_packets > 0,
+            "--queue should be at least one packet"
+        );
+        let link = |seed| Some(Link::new(self.imp.clone(), seed));
+        Relay {
+            listen: self.listen,
+            server: self.server,
+            impair_control: self.impair_control,
+            to_server: if self.direction.client_needs_sender() {
+                link(self.seed)
+            } else {
+                None
+            },
+            from_server: if self.direction.client_needs_receiver() {
+                link(self.seed.wrapping_add(1))
+            } else {
+                None
+            },
+        }
+        .run()
+    }
+}
+
+/// UDP relay between clients and a server
+pub struct Relay {
+    pub listen: SocketAddr,
+    pub server: SocketAddr,
+    /// Also impair control (negotiation and results) packets
+    pub impair_control: bool,
+    /// None means the direction is not impaired
+    pub to_server: Option<Link>,
+    pub from_server: Option<Link>,
+}
+
+impl Relay {
+    pub fn run(self) -> Result<()> {
+        let listen = UdpSocket::bind(self.listen)?;
+        println!("Relaying {} -> {}", self.listen, self.server);
+
+        let (tx, rx) = mpsc::channel::<Incoming>();
+        {
+            let listen = listen.try_clone()?;
+            let tx = tx.clone();
+            ::std::thread::spawn(move || -> Result<()> {
+                let mut buf = [0; 65536];
+                loop {
+                    let (ret, client) = listen.recv_from(&mut buf)?;
+                    let data = buf[..ret].to_vec();
+                    if tx
+                        .send(Incoming {
+                            dir: Dir::ToServer,
+                            client,
+                            data,
+                        })
+                        .is_err()
+                    {
+                        return Ok(());
+                    }
+                }
+            });
+        }
+
+        let mut links: HashMap<Dir, Link> = HashMap::new();
+        if let Some(l) = self.to_server {
+            links.insert(Dir::ToServer, l);
+        }
+        if let Some(l) = self.from_server {
+            links.insert(Dir::FromServer, l);
+        }
+
+        // One upstream socket per client, so that server sees them as different peers
+        let mut upstreams: HashMap<SocketAddr, Upstream> = HashMap::new();
+        let mut heap = BinaryHeap::<Outgoing>::new();
+        let mut seq = 0u64;
+        let mut last_report = Instant::now();
+
+        loop {
+            let timeout = match heap.peek() {
+                Some(x) => x.release.saturating_duration_since(Instant::now()),
+                None => Duration::from_secs(1),
+            };
+            match rx.recv_timeout(timeout) {
+                Ok(inc) => {
+                    if inc.dir == Dir::ToServer && !upstreams.contains_key(&inc.client) {
+                        let up = UdpSocket::bind(unspecified_like(self.server))?;
+                        up.connect(self.server)?;
+                        let up2 = up.try_clone()?;
+                        up2.set_read_timeout(Some(Duration::from_secs(1)))?;
+                        let alive = Arc::new(AtomicBool::new(true));
+                        let alive2 = alive.clone();
+                        let tx = tx.clone();
+                        let client = inc.client;
+                        ::std::thread::spawn(move || -> Result<()> {
+                            let mut buf = [0; 65536];
+                            loop {
+                                let ret = match up2.recv(&mut buf) {
+                                    Ok(x) => x,
+                                    Err(e)
+                                        if e.kind() == ErrorKind::WouldBlock
+                                            || e.kind() == ErrorKind::TimedOut =>
+                                    {
+                                        if !alive2.load(Ordering::Relaxed) {
+                                            return Ok(());
+                                        }
+                                        continue;
+                                    }
+                                    Err(e) => Err(e)?,
+                                };
+                                let data = buf[..ret].to_vec();
+                                if tx
+                                    .send(Incoming {
+                                        dir: Dir::FromServer,
+                                        client,
+                                        data,
+                                    })
+                                    .is_err()
+                                {
+                                    return Ok(());
+                                }
+                            }
+                        });
+                        println!("New client {}", inc.client);
+                        upstreams.insert(
+                            inc.client,
+                            Upstream {
+                                socket: up,
+                                last_active: Instant::now(),
+                                alive,
+                            },
+                        );
+                    }
+                    if let Some(up) = upstreams.get_mut(&inc.client) {
+                        up.last_active = Instant::now();
+                    }
+
+                    let now = Instant::now();
+                    let releases = match links.get_mut(&inc.dir) {
+                        Some(link) if self.impair_control || !is_control(&inc.data) => {
+                            match link.process(now, inc.data.len()) {
+                                Verdict::Drop => vec![],
+                                Verdict::Send(x) => x,
+                            }
+                        }
+                        _ => vec![now],
+                    };
+                    for release in releases {
+                        seq += 1;
+                        heap.push(Outgoing {
+                            release,
+                            seq,
+                            dir: inc.dir,
+                            client: inc.client,
+                            data: inc.data.clone(),
+                        });
+                    }
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => (),
+                Err(mpsc::RecvTimeoutError::Disconnected) => bail!("Relay threads exited"),
+            }
+
+            let now = Instant::now();
+            while heap.peek().is_some_and(|x| x.release <= now) {
+                let out = heap.pop().unwrap();
+                let r = match out.dir {
+                    Dir::ToServer => match upstreams.get(&out.client) {
+                        Some(up) => up.socket.send(&out.data),
+                        None => continue,
+                    },
+                    Dir::FromServer => listen.send_to(&out.data, out.client),
+                };
+                if let Err(e) = r {
+                    eprintln!("Error sending to {:?}: {}", out.dir, e);
+                }
+            }
+
+            if now - last_report > Duration::from_secs(10) {
+                last_report = now;
+                upstreams.retain(|client, up| {
+                    if now - up.last_active < CLIENT_IDLE_TIMEOUT {
+                        return true;
+                    }
+                    println!("Client {} expired", client);
+                    up.alive.store(false, Ordering::Relaxed);
+                    false
+                });
+                for (dir, link) in &links {
+                    if link.counters.received > 0 {
+                        println!("{:?}: {:?}", dir, link.counters);
+                    }
+                }
+            }
+        }
+    }
+}
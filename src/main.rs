@@ -39,7 +39,7 @@ extern crate bincode;
 
 extern crate itertools;
 
-const API_VERSION: u32 = 7;
+const API_VERSION: u32 = 19;
 
 use self::enum_unitary::EnumUnitary;
 
@@ -48,9 +48,14 @@ use self::structopt::StructOpt;
 use std::net::SocketAddr;
 
 pub mod battery;
+pub mod capacity;
 pub mod experiment;
+pub mod history;
+pub mod impair;
 pub mod probe;
+pub mod replay;
 pub mod serve;
+pub mod synth;
 
 pub type Result<T> = ::std::result::Result<T, ::anyhow::Error>;
 
@@ -63,6 +68,12 @@ struct ClientToServer {
     experiment: ExperimentInfo,
     api_version: u32,
     seqn_for_rtt: u32,
+    /// Request a chunk of raw receive data of the completed experiment instead of results
+    #[serde(default)]
+    raw_chunk: Option<u32>,
+    /// Request a part of results that don't fit a single datagram
+    #[serde(default)]
+    results_part: Option<u32>,
 }
 #[derive(Debug, Serialize, Deserialize)]
 struct ServerToClient {
@@ -78,6 +89,8 @@ impl From<(ExperimentInfo, u32)> for ClientToServer {
             experiment: x.0,
             api_version: API_VERSION,
             seqn_for_rtt: x.1,
+            raw_chunk: None,
+            results_part: None,
         }
     }
 }
@@ -101,6 +114,24 @@ enum Cmd {
     #[structopt(name = "probe")]
     Probe(probe::Cmd),
 
+    /// Find upload and download bottleneck rates using a series of short experiments
+    #[structopt(name = "capacity")]
+    Capacity(capacity::Cmd),
+
+    /// Relay UDP to a `serve` instance, adding delay, jitter, loss, reordering,
+    /// duplication and rate limiting
+    #[structopt(name = "impair")]
+    Impair(impair::Cmd),
+
+    /// Reproduce a network from saved results: relay UDP through its model
+    /// or print a `tc netem` script
+    #[structopt(name = "replay")]
+    Replay(replay::Cmd),
+
+    /// Generate raw data file (as saved by -R) from loss and delay models of saved results
+    #[structopt(name = "synth")]
+    Synth(synth::Cmd),
+
     RDump,
 
     /// Output statistics saved by -R option of probe or serve
@@ -108,6 +139,14 @@ enum Cmd {
     DumpSavedRawStats {
         #[structopt(parse(from_os_str))]
         file: ::std::path::PathBuf,
+
+        /// text | csv | pcap
+        #[structopt(long = "format", default_value = "text")]
+        format: experiment::rawfile::ExportFormat,
+
+        /// Write to this file instead of stdout
+        #[structopt(long = "output", short = "o", parse(from_os_str))]
+        output: Option<::std::path::PathBuf>,
     },
 
     /// Summarize data from -R rawdump
@@ -117,6 +156,21 @@ enum Cmd {
         file: ::std::path::PathBuf,
     },
 
+    /// Find netmeasure2 data packets in a libpcap capture and analyse each flow
+    #[structopt(name = "pcapanalyse")]
+    PcapAnalyse {
+        #[structopt(parse(from_os_str))]
+        file: ::std::path::PathBuf,
+
+        /// Ignore flows with less packets than this
+        #[structopt(long = "min-packets", default_value = "10")]
+        min_packets: usize,
+
+        /// Pacing the experiment was using
+        #[structopt(long = "pacing", default_value = "constant")]
+        pacing: experiment::pacing::Pacing,
+    },
+
     /// Visualise previous saved data
     #[structopt(name = "show")]
     Show {
@@ -135,6 +189,18 @@ enum Cmd {
     #[structopt(name = "showbat")]
     BatteryShow(battery::visualise::BatteryShow),
 
+    /// Write a built-in battery as a definition file for `battery --battery-file`
+    #[structopt(name = "batexport")]
+    BatteryExport(battery::definition::BatteryExport),
+
+    /// Compare two saved battery results
+    #[structopt(name = "batdiff")]
+    BatteryDiff(battery::diff::BatteryDiff),
+
+    /// Show score, loss and delay trends of runs recorded with --record
+    #[structopt(name = "history")]
+    History(history::Cmd),
+
     /// Try migrating battery results to new format
     #[structopt(name = "batmigrate")]
     BatteryMigrate {
@@ -149,16 +215,37 @@ fn main() -> Result<()> {
     match cmd {
         Cmd::Serve(x) => serve::serve(x)?,
         Cmd::Probe(x) => probe::probe(x)?,
+        Cmd::Capacity(x) => x.run()?,
+        Cmd::Impair(x) => x.run()?,
+        Cmd::Replay(x) => x.run()?,
+        Cmd::Synth(x) => x.run()?,
         Cmd::RDump => experiment::results::dump_some_results()?,
-        Cmd::DumpSavedRawStats { file } => {
-            experiment::receiver::PacketReceiver::dump_raw_data(&file)?
+        Cmd::DumpSavedRawStats {
+            file,
+            format,
+            output,
+        } => {
+            let out: Box<dyn ::std::io::Write> = match output {
+                Some(pb) => Box::new(::std::fs::File::create(pb)?),
+                None => Box::new(::std::io::stdout()),
+            };
+            let mut out = ::std::io::BufWriter::new(out);
+            experiment::rawfile::dump(&file, format, &mut out)?
         }
         Cmd::AnalyseRaw { file } => experiment::analyser::read_and_analyse(&file)?,
+        Cmd::PcapAnalyse {
+            file,
+            min_packets,
+            pacing,
+        } => experiment::pcap::analyse_capture(&file, min_packets, pacing)?,
         Cmd::Show { file } => experiment::visualiser::read_and_visualize(&file)?,
         Cmd::BatteryInfo => battery::Battery::generate().show(),
         Cmd::BatteryBBInfo => battery::Battery::generate_bb().show(),
         Cmd::Battery(x) => x.run()?,
         Cmd::BatteryShow(x) => x.run()?,
+        Cmd::BatteryExport(x) => x.run()?,
+        Cmd::BatteryDiff(x) => x.run()?,
+        Cmd::History(x) => x.run()?,
         Cmd::BatteryMigrate { file } => battery::visualise::migrate(&file)?,
     };
     Ok(())
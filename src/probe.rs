@@ -1,3 +1,5 @@
+use crate::experiment::rawfile::{RawDirection, RawHeader, RawRole};
+use crate::experiment::receiver::Info;
 use crate::experiment::results::{ExperimentResults, ResultsForStoring};
 use crate::experiment::statement::{ExperimentDirection, ExperimentInfo, ExperimentReply};
 use crate::experiment::SmallishDuration;
@@ -22,6 +24,11 @@ pub struct CommunicOpts {
     #[structopt(long = "save-raw-stats", short = "R", parse(from_os_str))]
     save_raw_stats: Option<::std::path::PathBuf>,
 
+    /// Also download server's raw receive data to `-R` directory
+    /// as `<session_id>.to_server.dat`
+    #[structopt(long = "fetch-raw")]
+    fetch_raw: bool,
+
     /// Maximum number of seconds to wait for results
     #[structopt(long = "max-wait-for-results", default_value = "15")]
     max_wait_for_results: u64,
@@ -48,9 +55,16 @@ pub struct Cmd {
     /// (maybe in addition to outputing JSON to `-o` file)
     #[structopt(short = "S")]
     visualise: bool,
+
+    #[structopt(flatten)]
+    record: crate::history::RecordOpts,
 }
 
 pub fn probe_impl(cmd: CmdImpl) -> Result<ResultsForStoring> {
+    ensure!(
+        !cmd.co.fetch_raw || cmd.co.save_raw_stats.is_some(),
+        "--fetch-raw requires -R"
+    );
     let udp = UdpSocket::bind(if cmd.co.ipv6 {
         SocketAddr::V6(SocketAddrV6::new(
             Ipv6Addr::UNSPECIFIED,
@@ -67,6 +81,8 @@ pub fn probe_impl(cmd: CmdImpl) -> Result<ResultsForStoring> {
         experiment: cmd.experiment,
         api_version: crate::API_VERSION,
         seqn_for_rtt: 0,
+        raw_chunk: None,
+        results_part: None,
     };
 
     let mut buf = [0; 1536];
@@ -138,7 +154,11 @@ pub fn probe_impl(cmd: CmdImpl) -> Result<ResultsForStoring> {
                         eprintln!("\nResource limits: {}", msg);
                         bail!("Parameters out of range");
                     }
-                    ExperimentReply::HereAreResults { .. } => bail!("Results not expected now"),
+                    ExperimentReply::HereAreResults { .. }
+                    | ExperimentReply::ResultsPart { .. } => {
+                        bail!("Results not expected now")
+                    }
+                    ExperimentReply::RawData { .. } => bail!("Raw data not expected now"),
                     ExperimentReply::RetryWithASessionId { session_id } => {
                         c2s.experiment.session_id = session_id;
                     }
@@ -163,6 +183,8 @@ pub fn probe_impl(cmd: CmdImpl) -> Result<ResultsForStoring> {
                 num_packets: c2s.experiment.totalpackets,
                 session_id: c2s.experiment.session_id,
                 experiment_start: experiment_start_for_receiver,
+                pacing: c2s.experiment.pacing,
+                payload: c2s.experiment.payload.clone(),
             },
         ))
     } else {
@@ -174,6 +196,9 @@ pub fn probe_impl(cmd: CmdImpl) -> Result<ResultsForStoring> {
         let serv2 = cmd.co.server;
         let sender = crate::experiment::sender::Sender {
             delay_between_packets: Duration::from_micros(c2s.experiment.packetdelay_us),
+            pacing: c2s.experiment.pacing,
+            profile: c2s.experiment.profile,
+            payload: c2s.experiment.payload.clone(),
             packetcount: c2s.experiment.totalpackets,
             packetsize: c2s.experiment.packetsize as usize,
             rtpmimic: c2s.experiment.rtpmimic,
@@ -191,6 +216,7 @@ pub fn probe_impl(cmd: CmdImpl) -> Result<ResultsForStoring> {
 
     let mut results_: Option<Rc<ExperimentResults>>;
     let send_lost_: Option<u32>;
+    let mut first_part = None;
 
     loop {
         let now = Instant::now();
@@ -207,7 +233,7 @@ pub fn probe_impl(cmd: CmdImpl) -> Result<ResultsForStoring> {
 
             if let Some(ref srs) = cmd.co.save_raw_stats {
                 if let Some(ref mut rcv) = rcv {
-                    rcv.save_raw_data(srs);
+                    rcv.save_raw_data(srs, &c2s.experiment, RawRole::Client);
                 }
             }
             end2 = now;
@@ -286,6 +312,20 @@ pub fn probe_impl(cmd: CmdImpl) -> Result<ResultsForStoring> {
                         results_ = stats;
                         break;
                     }
+                    ExperimentReply::ResultsPart {
+                        part: 0,
+                        parts,
+                        send_lost,
+                        data,
+                    } => {
+                        send_lost_ = send_lost;
+                        results_ = None;
+                        first_part = Some((parts, data));
+                        break;
+                    }
+                    ExperimentReply::RawData { .. } | ExperimentReply::ResultsPart { .. } => {
+                        continue;
+                    }
                     ExperimentReply::RetryWithASessionId { session_id: _ } => {
                         bail!("Unexpected retryWSId")
                     }
@@ -312,8 +352,29 @@ pub fn probe_impl(cmd: CmdImpl) -> Result<ResultsForStoring> {
             Err(e) => Err(e)?,
         }
     }
+    if let Some((parts, data)) = first_part {
+        results_ = fetch_results(&udp, cmd.co.server, &mut c2s, &mut buf, parts, data)?;
+        if let Some(ref x) = results_ {
+            ensure!(
+                x.session_id == c2s.experiment.session_id,
+                "wrong session id in results"
+            );
+        }
+    }
     eprintln!("Results received");
 
+    if let Some(ref srs) = cmd.co.save_raw_stats {
+        if cmd.co.fetch_raw && c2s.experiment.direction.server_needs_receiver() {
+            let (total, v) = fetch_raw_data(&udp, cmd.co.server, &mut c2s, &mut buf)?;
+            let p = srs.join(format!("{}.to_server.dat", c2s.experiment.session_id));
+            let mut header =
+                RawHeader::new(&c2s.experiment, RawRole::Server, RawDirection::ToServer);
+            header.total_packets = total as u64;
+            crate::experiment::rawfile::save(&p, &header, &v)?;
+            eprintln!("Server's raw data saved");
+        }
+    }
+
     let mut my_send_lost = None;
     if let Some(snd) = snd {
         match snd.join() {
@@ -360,7 +421,127 @@ pub fn probe_impl(cmd: CmdImpl) -> Result<ResultsForStoring> {
     Ok(final_result)
 }
 
-pub fn probe(cmd: Cmd) -> Result<()> {
+/// Download the rest of results that don't fit a single datagram, part by part
+fn fetch_results(
+    udp: &UdpSocket,
+    server: SocketAddr,
+    c2s: &mut crate::ClientToServer,
+    buf: &mut [u8],
+    parts: u32,
+    mut data: Vec<u8>,
+) -> Result<Option<Rc<ExperimentResults>>> {
+    let mut part = 1;
+    let mut timeouts = 0;
+    while part < parts {
+        c2s.results_part = Some(part);
+        c2s.seqn_for_rtt += 1;
+        udp.send_to(::serde_cbor::ser::to_vec_sd(&c2s)?.as_slice(), server)?;
+        let ret = match udp.recv_from(buf) {
+            Ok((ret, from)) if from == server => ret,
+            Ok(_) => continue,
+            Err(ref e) if e.kind() == ::std::io::ErrorKind::WouldBlock => {
+                timeouts += 1;
+                if timeouts > 10 {
+                    bail!("Timed out fetching results");
+                }
+                continue;
+            }
+            Err(e) => Err(e)?,
+        };
+        let msg = &buf[0..ret];
+        if ret < crate::experiment::statement::MINPACKETSIZE || &msg[0..3] != b"\xd9\xd9\xf7" {
+            continue;
+        }
+        let s2c: crate::ServerToClient = ::serde_cbor::from_slice(msg)?;
+        match s2c.reply {
+            ExperimentReply::ResultsPart {
+                part: p, data: d, ..
+            } if p == part => {
+                data.extend_from_slice(&d);
+                timeouts = 0;
+                part += 1;
+            }
+            ExperimentReply::Failed { msg } => {
+                eprintln!("{}", msg);
+                bail!("Fail reply from server while fetching results");
+            }
+            _ => continue,
+        }
+    }
+    c2s.results_part = None;
+    Ok(::serde_cbor::from_slice(&data)?)
+}
+
+/// Download raw receive data of the completed experiment from server, chunk by chunk
+fn fetch_raw_data(
+    udp: &UdpSocket,
+    server: SocketAddr,
+    c2s: &mut crate::ClientToServer,
+    buf: &mut [u8],
+) -> Result<(usize, Vec<Info>)> {
+    let mut v = vec![];
+    let mut chunk = 0;
+    let mut timeouts = 0;
+    eprint!("Fetching raw data");
+    loop {
+        c2s.raw_chunk = Some(chunk);
+        c2s.seqn_for_rtt += 1;
+        udp.send_to(::serde_cbor::ser::to_vec_sd(&c2s)?.as_slice(), server)?;
+        let ret = match udp.recv_from(buf) {
+            Ok((ret, from)) if from == server => ret,
+            Ok(_) => continue,
+            Err(ref e) if e.kind() == ::std::io::ErrorKind::WouldBlock => {
+                timeouts += 1;
+                if timeouts > 10 {
+                    eprintln!(" timeout");
+                    bail!("Timed out fetching raw data");
+                }
+                continue;
+            }
+            Err(e) => Err(e)?,
+        };
+        let msg = &buf[0..ret];
+        if ret < crate::experiment::statement::MINPACKETSIZE || &msg[0..3] != b"\xd9\xd9\xf7" {
+            continue;
+        }
+        let s2c: crate::ServerToClient = ::serde_cbor::from_slice(msg)?;
+        match s2c.reply {
+            ExperimentReply::RawData {
+                offset,
+                received,
+                total_packets,
+                records,
+            } => {
+                if offset as usize != v.len() {
+                    continue;
+                }
+                v.extend(records.chunks_exact(3).map(|x| Info {
+                    seqn: x[0],
+                    st_us: x[1],
+                    rt_us: x[2],
+                }));
+                eprint!(".");
+                timeouts = 0;
+                chunk += 1;
+                if v.len() >= received as usize || records.is_empty() {
+                    eprintln!();
+                    return Ok((total_packets as usize, v));
+                }
+            }
+            ExperimentReply::Failed { msg } => {
+                eprintln!("{}", msg);
+                bail!("Fail reply from server while fetching raw data");
+            }
+            _ => continue,
+        }
+    }
+}
+
+pub fn probe(mut cmd: Cmd) -> Result<()> {
+    if let Some(profile) = cmd.inner.experiment.profile {
+        profile.apply(&mut cmd.inner.experiment);
+    }
+    let server = cmd.inner.co.server;
     let final_result = probe_impl(cmd.inner)?;
 
     if cmd.visualise && cmd.output.is_none() {
@@ -380,6 +561,17 @@ pub fn probe(cmd: Cmd) -> Result<()> {
         ::serde_json::ser::to_writer(&mut out, &final_result)?;
         use ::std::io::Write;
         writeln!(out)?;
+        out.flush()?;
+    }
+
+    // Results are already out, so a broken history store should not lose them
+    if let Err(e) = crate::history::record(
+        &cmd.record,
+        &server,
+        crate::history::RunKind::Probe,
+        ::std::slice::from_ref(&final_result),
+    ) {
+        eprintln!("Warning: failed to record to history: {}", e);
     }
 
     Ok(())
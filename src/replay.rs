@@ -0,0 +1,162 @@
+//! Reproduce a measured network: relay traffic through a model built from saved results,
+//! or print a `tc netem` script approximating it
+
+use crate::experiment::generator::TraceGenerator;
+use crate::experiment::results::{ExperimentResults, ResultsForStoring};
+use crate::impair::{Impairments, Link, Relay};
+use crate::Result;
+use ::std::net::SocketAddr;
+use ::structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct Cmd {
+    /// Results saved by `probe -o` or `battery -o`
+    #[structopt(parse(from_os_str))]
+    file: ::std::path::PathBuf,
+
+    /// Which experiment of a battery file to use, counting from 0 in file order
+    #[structopt(long = "entry")]
+    entry: Option<usize>,
+
+    /// Print `tc netem` script approximating the network instead of relaying
+    #[structopt(long = "netem")]
+    netem: bool,
+
+    /// UDP port to accept clients on
+    #[structopt(long = "listen")]
+    listen: Option<SocketAddr>,
+
+    /// `netmeasure2 serve` instance (or anything else) to relay to
+    #[structopt(long = "server")]
+    server: Option<SocketAddr>,
+
+    /// Also impair control (negotiation and results) packets, not only data packets
+    #[structopt(long = "impair-control")]
+    impair_control: bool,
+
+    /// Seed for random decisions, for reproducible runs
+    #[structopt(long = "seed", default_value = "1")]
+    seed: u64,
+}
+
+pub fn load_entry(p: &::std::path::Path, entry: Option<usize>) -> Result<ResultsForStoring> {
+    let f = ::std::io::BufReader::new(::std::fs::File::open(p)?);
+    let v: ::serde_json::Value = ::serde_json::from_reader(f)?;
+    if v.is_array() {
+        let mut v: Vec<ResultsForStoring> = ::serde_json::from_value(v)?;
+        let i = match entry {
+            Some(i) => i,
+            None if v.len() == 1 => 0,
+            None => bail!("There are {} experiments in the file, use --entry", v.len()),
+        };
+        ensure!(i < v.len(), "No entry {} in the file", i);
+        Ok(v.swap_remove(i))
+    } else {
+        ensure!(entry.unwrap_or(0) == 0, "Not a battery file");
+        Ok(::serde_json::from_value(v)?)
+    }
+}
+
+/// `tc qdisc ... netem` arguments approximating one direction
+fn netem_args(r: &ExperimentResults, base_delay_ms: f32) -> String {
+    let dm = &r.delay_model;
+    let lm = &r.loss_model;
+    let mut args = vec![];
+
+    // Normal distribution with the same median and 90th percentile
+    let sigma_ms = ((dm.p90_ms - dm.p50_ms) / 1.2816).max(0.0);
+    if sigma_ms >= 0.1 {
+        args.push(format!(
+            "delay {:.1}ms {:.1}ms distribution normal",
+            base_delay_ms + dm.p50_ms - dm.min_ms,
+            sigma_ms
+        ));
+    } else {
+        args.push(format!(
+            "delay {:.1}ms",
+            base_delay_ms + dm.p50_ms - dm.min_ms
+        ));
+    }
+
+    if lm.loss_prob > 0.0 {
+        if let Some(ge) = lm
+            .gilbert_elliott
+            .or(lm.gilbert)
+            .or_else(|| lm.gilbert_from_clusters())
+        {
+            args.push(ge.netem());
+        }
+    }
+    if r.reorder_model.reorder_prob > 0.0 {
+        args.push(format!(
+            "reorder {:.3}%",
+            r.reorder_model.reorder_prob * 100.0
+        ));
+    }
+    if lm.duplicate_prob > 0.0 {
+        args.push(format!("duplicate {:.3}%", lm.duplicate_prob * 100.0));
+    }
+    args.join(" ")
+}
+
+fn print_netem_script(rfs: &ResultsForStoring, base_delay_ms: f32) {
+    println!("#!/bin/sh");
+    println!("# Approximation of the network measured by netmeasure2.");
+    println!("# Run on the client host (or a router in front of it): $0 <interface>");
+    println!("set -e");
+    println!("DEV=\"${{1:-eth0}}\"");
+    if let Some(ref r) = rfs.to_server {
+        println!();
+        println!("# Client to server: egress of $DEV");
+        println!(
+            "tc qdisc replace dev \"$DEV\" root netem {}",
+            netem_args(r, base_delay_ms)
+        );
+    }
+    if let Some(ref r) = rfs.from_server {
+        println!();
+        println!("# Server to client: ingress of $DEV, redirected through ifb0");
+        println!("modprobe ifb");
+        println!("ip link set dev ifb0 up");
+        println!("tc qdisc replace dev \"$DEV\" ingress");
+        println!("tc filter replace dev \"$DEV\" parent ffff: protocol all u32 match u32 0 0 action mirred egress redirect dev ifb0");
+        println!(
+            "tc qdisc replace dev ifb0 root netem {}",
+            netem_args(r, base_delay_ms)
+        );
+    }
+}
+
+impl Cmd {
+    pub fn run(self) -> Result<()> {
+        let rfs = load_entry(&self.file, self.entry)?;
+        // Results only have delays relative to the fastest packet
+        let base_delay_ms = rfs.rtt_us as f32 / 2000.0;
+
+        if self.netem {
+            print_netem_script(&rfs, base_delay_ms);
+            return Ok(());
+        }
+
+        let (listen, server) = match (self.listen, self.server) {
+            (Some(l), Some(s)) => (l, s),
+            _ => bail!("Specify --listen and --server to relay, or --netem to print a script"),
+        };
+        let imp = Impairments::from_iter(&["replay", "--delay", &base_delay_ms.to_string()]);
+        let link = |r: &ExperimentResults, seed| {
+            Link::new(imp.clone(), seed).with_model(TraceGenerator::new(r, seed))
+        };
+        println!("Replaying {:?}", rfs.conditions);
+        Relay {
+            listen,
+            server,
+            impair_control: self.impair_control,
+            to_server: rfs.to_server.as_ref().map(|r| link(r, self.seed)),
+            from_server: rfs
+                .from_server
+                .as_ref()
+                .map(|r| link(r, self.seed.wrapping_add(1))),
+        }
+        .run()
+    }
+}
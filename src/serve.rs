@@ -9,9 +9,12 @@ use ::std::net::UdpSocket;
 
 use ::serde_cbor::{de::from_slice, ser::to_vec_sd};
 
-use crate::experiment::receiver::{PacketReceiver, PacketReceiverParams};
-use crate::experiment::results::ExperimentResults;
-use crate::experiment::statement::{ExperimentInfo, ExperimentReply, MINPACKETSIZE};
+use crate::experiment::rawfile::RawRole;
+use crate::experiment::receiver::{Info, PacketReceiver, PacketReceiverParams};
+use crate::experiment::results::{ExperimentResults, MAX_RESULTS_DATAGRAM, RESULTS_PART_BYTES};
+use crate::experiment::statement::{
+    ExperimentInfo, ExperimentReply, MINPACKETSIZE, RAW_CHUNK_RECORDS,
+};
 
 use ::rand::Rng;
 
@@ -45,6 +48,49 @@ struct CompletedExperiment {
     info: ExperimentInfo,
     rcv: Option<Rc<ExperimentResults>>,
     snd: Option<u32>,
+    /// Total packets and raw receive data, for `raw_chunk` requests
+    raw: Option<(u32, Vec<Info>)>,
+}
+
+impl CompletedExperiment {
+    /// Results as a whole if they fit a datagram, otherwise the requested part of them
+    fn results(&self, part: Option<u32>) -> Result<ExperimentReply> {
+        let data = to_vec_sd(&self.rcv)?;
+        if part.is_none() && data.len() <= RESULTS_PART_BYTES {
+            return Ok(ExperimentReply::HereAreResults {
+                stats: self.rcv.clone(),
+                send_lost: self.snd,
+            });
+        }
+        let parts = data.len().div_ceil(RESULTS_PART_BYTES);
+        let part = (part.unwrap_or(0) as usize).min(parts - 1);
+        let offset = part * RESULTS_PART_BYTES;
+        let end = (offset + RESULTS_PART_BYTES).min(data.len());
+        Ok(ExperimentReply::ResultsPart {
+            part: part as u32,
+            parts: parts as u32,
+            send_lost: self.snd,
+            data: data[offset..end].to_vec(),
+        })
+    }
+
+    fn raw_chunk(&self, chunk: u32) -> ExperimentReply {
+        let (total_packets, v) = match self.raw {
+            Some((total, ref v)) => (total, &v[..]),
+            None => (0, &[][..]),
+        };
+        let offset = (chunk as usize * RAW_CHUNK_RECORDS).min(v.len());
+        let end = (offset + RAW_CHUNK_RECORDS).min(v.len());
+        ExperimentReply::RawData {
+            offset: offset as u32,
+            received: v.len() as u32,
+            total_packets,
+            records: v[offset..end]
+                .iter()
+                .flat_map(|x| vec![x.seqn, x.st_us, x.rt_us])
+                .collect(),
+        }
+    }
 }
 
 struct OngoingExperiment {
@@ -95,18 +141,20 @@ impl State {
                 let mut ce;
                 if let Some(ref mut rcv) = oe.rcv {
                     if let Some(srs) = cmd.save_raw_stats.as_ref() {
-                        rcv.save_raw_data(srs);
+                        rcv.save_raw_data(srs, &oe.info, RawRole::Server);
                     }
                     ce = CompletedExperiment {
                         info: oe.info.clone(),
                         rcv: Some(Rc::new(rcv.analyse())),
                         snd: None,
+                        raw: Some((rcv.total_packets() as u32, rcv.received().to_vec())),
                     };
                 } else {
                     ce = CompletedExperiment {
                         info: oe.info.clone(),
                         rcv: None,
                         snd: None,
+                        raw: None,
                     };
                 }
 
@@ -153,6 +201,9 @@ impl State {
                 let snd = if rq.direction.server_needs_sender() {
                     let sender = crate::experiment::sender::Sender {
                         delay_between_packets: Duration::from_micros(rq.packetdelay_us),
+                        pacing: rq.pacing,
+                        profile: rq.profile,
+                        payload: rq.payload.clone(),
                         packetsize: rq.packetsize as usize,
                         rtpmimic: rq.rtpmimic,
                         packetcount: rq.totalpackets,
@@ -170,6 +221,8 @@ impl State {
                         experiment_start,
                         session_id: rq.session_id,
                         num_packets: rq.totalpackets,
+                        pacing: rq.pacing,
+                        payload: rq.payload.clone(),
                     };
                     Some(PacketReceiver::new(prp))
                 } else {
@@ -220,7 +273,7 @@ pub fn serve(cmd: Cmd) -> Result<()> {
                     };
                     continue;
                 }
-                Err(e) => Err(e)?,
+                Err(e) => Err(::anyhow::Error::from(e))?,
             };
             prev_cla = Some(cla);
             let msg = &buf[0..ret];
@@ -232,7 +285,7 @@ pub fn serve(cmd: Cmd) -> Result<()> {
                     if &msg[0..3] != b"\xd9\xd9\xf7" {
                         continue;
                     }
-                    let s2c: super::ClientToServer = from_slice(msg)?;
+                    let s2c: super::ClientToServer = from_slice(msg).map_err(::anyhow::Error::from)?;
                     if s2c.api_version != crate::API_VERSION {
                         println!("Invalid API version");
                         continue;
@@ -241,11 +294,12 @@ pub fn serve(cmd: Cmd) -> Result<()> {
                     let seqn_for_rtt = s2c.seqn_for_rtt;
 
                     let rp;
-                    if laste.is_some() && laste.as_ref().unwrap().info == rq {
-                        rp = ExperimentReply::HereAreResults {
-                            stats: laste.as_ref().unwrap().rcv.clone(),
-                            send_lost: laste.as_ref().unwrap().snd,
-                        };
+                    if let (Some(le), Some(chunk)) =
+                        (laste.as_ref().filter(|x| x.info == rq), s2c.raw_chunk)
+                    {
+                        rp = le.raw_chunk(chunk);
+                    } else if laste.is_some() && laste.as_ref().unwrap().info == rq {
+                        rp = laste.as_ref().unwrap().results(s2c.results_part)?;
                     } else if let Err(e) = rq.check_limits(&cmd) {
                         rp = ExperimentReply::ResourceLimits { msg: e.to_string() };
                     } else if pending
@@ -290,7 +344,7 @@ pub fn serve(cmd: Cmd) -> Result<()> {
                     }
 
                     if &msg[0..3] == b"\xd9\xd9\xf7" {
-                        let s2c: super::ClientToServer = from_slice(msg)?;
+                        let s2c: super::ClientToServer = from_slice(msg).map_err(::anyhow::Error::from)?;
                         if s2c.api_version != crate::API_VERSION {
                             bail!("Invalid API version");
                         }
@@ -371,7 +425,15 @@ trait ExperimentNegotiation {
 impl ExperimentNegotiation for UdpSocket {
     fn reply(&mut self, rp: ExperimentReply, cla: SocketAddr, seqn_for_rtt: u32) -> Result<()> {
         let s2c = crate::ServerToClient::from((rp, seqn_for_rtt));
-        self.send_to(&to_vec_sd(&s2c)?[..], cla)?;
+        let mut b = to_vec_sd(&s2c)?;
+        if b.len() > MAX_RESULTS_DATAGRAM {
+            println!("Reply of {} bytes is too big", b.len());
+            let rp = ExperimentReply::Failed {
+                msg: "reply too big".to_string(),
+            };
+            b = to_vec_sd(&crate::ServerToClient::from((rp, seqn_for_rtt)))?;
+        }
+        self.send_to(&b[..], cla)?;
         Ok(())
     }
 }
@@ -398,6 +460,9 @@ impl ExperimentInfo {
             return Err("invalid packetsize");
         }
 
+        self.pacing.check_limits()?;
+        self.payload.check_limits()?;
+
         if self.pending_start_in_microseconds > 5_000_000 {
             return Err("pending start too late");
         }
@@ -412,16 +477,33 @@ impl ExperimentInfo {
     }
 
     pub fn duration(&self) -> Duration {
-        Duration::from_micros(self.packetdelay_us) * self.totalpackets
+        self.pacing.offset(self.totalpackets, self.packetdelay_us)
+    }
+
+    /// Average packet size, taking traffic profile into account
+    pub fn mean_packetsize(&self) -> u32 {
+        match self.profile {
+            Some(p) => p.mean_packetsize().min(self.packetsize),
+            None => self.packetsize,
+        }
     }
 
     pub fn kbps(&self) -> u32 {
-        let effective_ps = (self.packetsize + 32).max(64);
-        let pps = 1000_000.0 / (self.packetdelay_us as f64);
+        let effective_ps = (self.mean_packetsize() + 32).max(64);
+        let pps = 1000_000.0 / self.pacing.mean_packetdelay_us(self.packetdelay_us);
         ((effective_ps as f64) * pps * 8.0 / 1000.0) as u32
     }
 
-    pub fn bytes_used(&self) -> u32 {
-        self.totalpackets * (self.packetsize + 24)
+    pub fn bytes_used(&self) -> u64 {
+        self.totalpackets as u64 * (self.mean_packetsize() as u64 + 24)
+    }
+
+    /// Whether both describe the same experiment, regardless of session
+    pub fn same_conditions(&self, other: &ExperimentInfo) -> bool {
+        let x = ExperimentInfo {
+            session_id: other.session_id,
+            ..self.clone()
+        };
+        x == *other
     }
 }
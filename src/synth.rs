@@ -0,0 +1,134 @@
+//! Synthetic raw data files, sampled from loss and delay models of saved results
+
+use crate::experiment::analyser::analyse;
+use crate::experiment::generator::synthesize;
+use crate::experiment::rawfile::{self, RawDirection, RawHeader, RawRole, TimestampSource};
+use crate::experiment::results::ExperimentResults;
+use crate::Result;
+use ::structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct Cmd {
+    /// Results saved by `probe -o` or `battery -o`
+    #[structopt(parse(from_os_str))]
+    file: ::std::path::PathBuf,
+
+    /// Raw data file to write, readable by `rawdump` and `analyse`
+    #[structopt(short = "o", long = "output", parse(from_os_str))]
+    output: ::std::path::PathBuf,
+
+    /// Which experiment of a battery file to use, counting from 0 in file order
+    #[structopt(long = "entry")]
+    entry: Option<usize>,
+
+    /// Use server to client direction instead of client to server one
+    #[structopt(long = "from-server")]
+    from_server: bool,
+
+    /// Number of packets to generate instead of the experiment's one
+    #[structopt(long = "totalpackets")]
+    totalpackets: Option<u32>,
+
+    /// Seed for random decisions, for reproducible output
+    #[structopt(long = "seed", default_value = "1")]
+    seed: u64,
+
+    /// Analyse generated data and compare it with the original results
+    #[structopt(long = "compare")]
+    compare: bool,
+}
+
+/// Total variation distance between two normalized histograms, from 0 (same) to 1
+fn distance(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| (x - y).abs()).sum::<f32>() / 2.0
+}
+
+fn print_comparison(orig: &ExperimentResults, synth: &ExperimentResults) {
+    let row = |name: &str, a: f32, b: f32| println!("{:<28}{:>12.3}{:>12.3}", name, a, b);
+    println!("{:<28}{:>12}{:>12}", "", "original", "synthetic");
+    row(
+        "loss, %",
+        orig.loss_model.loss_prob * 100.0,
+        synth.loss_model.loss_prob * 100.0,
+    );
+    row(
+        "mean loss burst, packets",
+        orig.loss_model.mean_loss_burst(),
+        synth.loss_model.mean_loss_burst(),
+    );
+    row(
+        "duplicates, %",
+        orig.loss_model.duplicate_prob * 100.0,
+        synth.loss_model.duplicate_prob * 100.0,
+    );
+    row(
+        "reordered, %",
+        orig.reorder_model.reorder_prob * 100.0,
+        synth.reorder_model.reorder_prob * 100.0,
+    );
+    let (od, sd) = (&orig.delay_model, &synth.delay_model);
+    row("mean delay, ms", od.mean_delay_ms, sd.mean_delay_ms);
+    row("delay p50, ms", od.p50_ms, sd.p50_ms);
+    row("delay p90, ms", od.p90_ms, sd.p90_ms);
+    row("delay p99, ms", od.p99_ms, sd.p99_ms);
+    row("jitter, ms", od.jitter_ms, sd.jitter_ms);
+
+    println!();
+    println!("Histogram distances (0 = same, 1 = disjoint):");
+    let hist = |name: &str, a: &[f32], b: &[f32]| println!("{:<28}{:>12.3}", name, distance(a, b));
+    hist(
+        "loss clusters",
+        &orig.loss_model.loss,
+        &synth.loss_model.loss,
+    );
+    hist(
+        "non-loss clusters",
+        &orig.loss_model.nonloss,
+        &synth.loss_model.nonloss,
+    );
+    hist("delay values", &od.value_popularity, &sd.value_popularity);
+    hist("delay jumps", &od.delta_noloss, &sd.delta_noloss);
+    hist(
+        "reorder extents",
+        &orig.reorder_model.extent,
+        &synth.reorder_model.extent,
+    );
+}
+
+impl Cmd {
+    pub fn run(self) -> Result<()> {
+        let rfs = crate::replay::load_entry(&self.file, self.entry)?;
+        // Data sent to server is recorded by the server
+        let (r, direction, role) = if self.from_server {
+            (&rfs.from_server, RawDirection::FromServer, RawRole::Client)
+        } else {
+            (&rfs.to_server, RawDirection::ToServer, RawRole::Server)
+        };
+        let r = match r {
+            Some(r) => r,
+            None => bail!("No results for {:?} direction in the file", direction),
+        };
+
+        let mut conditions = rfs.conditions.clone();
+        if let Some(n) = self.totalpackets {
+            conditions.totalpackets = n;
+        }
+        let v = synthesize(r, &conditions, self.seed);
+
+        let mut header = RawHeader::new(&conditions, role, direction);
+        header.timestamp_source = TimestampSource::Synthetic;
+        rawfile::save(&self.output, &header, &v)?;
+        println!(
+            "Generated {} records of {} packets",
+            v.len(),
+            conditions.totalpackets
+        );
+
+        if self.compare {
+            let synth = analyse(&v, conditions.totalpackets as usize, conditions.pacing);
+            println!();
+            print_comparison(r, &synth);
+        }
+        Ok(())
+    }
+}